@@ -0,0 +1,239 @@
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{Error, Result},
+    ffi::{self, Lstat, MountPoint},
+    home,
+    trash::{self, Trash},
+};
+use unixstring::UnixString;
+
+/// Where the current time is taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// The system's real-time clock
+    System,
+    /// Always the given duration since the Unix epoch
+    Fixed(Duration),
+}
+
+impl Clock {
+    /// The current time, as the duration since the Unix epoch
+    pub fn now(&self) -> Duration {
+        match self {
+            Clock::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("it seems that time went backwards!"),
+            Clock::Fixed(now) => *now,
+        }
+    }
+}
+
+/// Everything `tt` needs to know about the environment it runs in: the user's home
+/// and data directories, the mount points whose `$topdir` may hold trash directories,
+/// the user's ID and a clock.
+///
+/// `TrashContext::from_env` describes the calling user's environment, while
+/// `TrashContext::new` (or setting the fields directly) allows pointing `tt`
+/// at alternate roots.
+#[derive(Debug, Clone)]
+pub struct TrashContext {
+    pub home_dir: PathBuf,
+    /// `$XDG_DATA_HOME`, in which the home trash resides
+    pub xdg_data_dir: PathBuf,
+    /// Expected to be sorted by decreasing path length, as returned by `probe_mount_points`
+    pub mount_points: Vec<MountPoint>,
+    pub uid: u32,
+    pub clock: Clock,
+}
+
+impl TrashContext {
+    /// A context whose `$XDG_DATA_HOME` is the default `$HOME/.local/share`
+    /// and whose clock is the system's.
+    pub fn new(home_dir: &Path, mount_points: Vec<MountPoint>, uid: u32) -> Self {
+        Self {
+            home_dir: home_dir.to_owned(),
+            xdg_data_dir: home::default_xdg_data_dir(home_dir),
+            mount_points,
+            uid,
+            clock: Clock::System,
+        }
+    }
+
+    /// The context of the calling user, as given by `$HOME`, `$XDG_DATA_HOME`,
+    /// the system's mount table and the effective user ID.
+    pub fn from_env() -> Result<Self> {
+        let home_dir = home::home_dir().ok_or(Error::HomeDirNotFound)?;
+
+        Ok(Self {
+            xdg_data_dir: home::xdg_data_dir(&home_dir),
+            ..Self::new(
+                &home_dir,
+                ffi::probe_mount_points()?,
+                ffi::effective_user_id(),
+            )
+        })
+    }
+
+    /// The home trash, in `$XDG_DATA_HOME/Trash`
+    pub fn home_trash(&self) -> Trash {
+        Trash::new(&home::home_trash_path(self))
+    }
+
+    /// The mount point the file given by `path` resides in.
+    ///
+    /// This is the deepest mount point containing `path` whose topdir is on the same
+    /// mount as the file itself (see `FileMount`), so that moving the file into a trash
+    /// directory in that topdir never crosses filesystems, even through bind or overlay mounts.
+    /// Assumes that the parent of `path` is canonicalized (see `trash::resolve_parent`).
+    pub fn mount_point_of_file(&self, path: &Path) -> Result<&MountPoint> {
+        let file_mount = FileMount::of(path)?;
+
+        self.mount_points
+            .iter()
+            .filter(|mount_point| mount_point.contains(path))
+            .find(|mount_point| {
+                FileMount::of(&mount_point.fs_path_prefix)
+                    .is_ok_and(|topdir_mount| topdir_mount.is_same_mount(&file_mount))
+            })
+            .ok_or_else(|| Error::MountPointNotFound(path.to_owned()))
+    }
+
+    /// The ID of the device the home trash resides (or would reside) in.
+    ///
+    /// The home trash may not have been created yet, in which case its
+    /// closest existing ancestor is used.
+    pub fn home_trash_device_id(&self) -> Result<u64> {
+        existing_ancestor_device_id(&home::home_trash_path(self))
+    }
+
+    /// The trash directory the file given by `path` should be sent to,
+    /// which gets created if needed. Assumes that the parent of `path` is canonicalized.
+    ///
+    /// Files on the same mount as the home trash go to the home trash,
+    /// wherever the home directory is. Other files go to the trash directory
+    /// of the mount point they reside in.
+    pub fn trash_for(&self, path: &Path) -> Result<Trash> {
+        let trash = self.locate_trash_for(path)?;
+        trash.ensure_exists(self.uid)?;
+
+        Ok(trash)
+    }
+
+    /// Same as `trash_for`, without creating anything
+    pub fn locate_trash_for(&self, path: &Path) -> Result<Trash> {
+        let file_mount = FileMount::of(path)?;
+        let home_trash_mount = FileMount::of_existing_ancestor(&home::home_trash_path(self));
+
+        if home_trash_mount
+            .is_ok_and(|home_trash_mount| home_trash_mount.is_same_mount(&file_mount))
+        {
+            return Ok(self.home_trash());
+        }
+
+        let topdir = &self.mount_point_of_file(path)?.fs_path_prefix;
+
+        Ok(Trash::with_topdir(
+            &trash::topdir_trash_path(topdir, self.uid),
+            topdir,
+        ))
+    }
+
+    /// Why the administrator-provided `$topdir/.Trash` of some mount points can't be used,
+    /// as `Error::InvalidSharedTrash`. Their `$topdir/.Trash-$uid` is used instead.
+    pub fn shared_trash_problems(&self) -> Vec<Error> {
+        let mut seen = HashSet::new();
+
+        self.mount_points
+            .iter()
+            .map(|mount_point| &mount_point.fs_path_prefix)
+            .filter(|topdir| seen.insert(*topdir))
+            .filter_map(|topdir| trash::shared_trash_problem(topdir, self.uid))
+            .collect()
+    }
+
+    /// The home trash followed by the trash directories
+    /// already present in every mount point.
+    ///
+    /// Every trash directory is listed once, even if it can be reached through
+    /// several mount points (e.g. stacked mounts, or the home trash in a topdir).
+    pub fn all_trashes(&self) -> Vec<Trash> {
+        let topdir_trashes = self.mount_points.iter().flat_map(|mount_point| {
+            trash::existing_topdir_trashes(&mount_point.fs_path_prefix, self.uid)
+        });
+
+        let mut seen = HashSet::new();
+
+        std::iter::once(self.home_trash())
+            .filter(|trash| trash.info.is_dir())
+            .chain(topdir_trashes)
+            // Identified by device and inode, which don't depend on the path taken
+            .filter(|trash| match fs::metadata(&trash.root) {
+                Ok(metadata) => seen.insert((metadata.dev(), metadata.ino())),
+                Err(_) => true,
+            })
+            .collect()
+    }
+}
+
+/// The mount a file is reached through, which must be the same for the file and a trash
+/// directory for rename(2) to move the file there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMount {
+    /// The ID of the device containing the file
+    device_id: u64,
+    /// The ID of the mount, telling apart the bind mounts of a same filesystem,
+    /// if the kernel can tell
+    mount_id: Option<u64>,
+}
+
+impl FileMount {
+    /// The mount of the file given by `path`. Symbolic links are not followed.
+    pub fn of(path: &Path) -> Result<Self> {
+        let path = UnixString::try_from(path.to_owned())?;
+
+        Ok(Self {
+            device_id: Lstat::lstat(&path)?.device_id(),
+            mount_id: ffi::mount_id(&path)?,
+        })
+    }
+
+    /// The mount of `path` or, if it doesn't exist yet, of its closest existing ancestor
+    pub fn of_existing_ancestor(path: &Path) -> Result<Self> {
+        let existing_ancestor = path
+            .ancestors()
+            .find(|ancestor| ancestor.exists())
+            .ok_or(Error::HomeDirNotFound)?;
+
+        // Following a final symbolic link, as `exists` did
+        Self::of(&existing_ancestor.canonicalize()?)
+    }
+
+    /// Whether a file can be renamed from one of the mounts to the other
+    pub fn is_same_mount(&self, other: &Self) -> bool {
+        self.device_id == other.device_id
+            && match (self.mount_id, other.mount_id) {
+                (Some(mount_id), Some(other_mount_id)) => mount_id == other_mount_id,
+                // Only the device can be compared
+                _ => true,
+            }
+    }
+}
+
+/// The ID of the device containing `path` or, if it doesn't exist yet,
+/// its closest existing ancestor
+pub fn existing_ancestor_device_id(path: &Path) -> Result<u64> {
+    let existing_ancestor = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .ok_or(Error::HomeDirNotFound)?;
+
+    Ok(fs::metadata(existing_ancestor)?.dev())
+}
@@ -0,0 +1,275 @@
+use std::{
+    ffi::OsStr,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    error::{Error, Result},
+    ffi::Dir,
+    info_file::{make_info_file_name, TrashEntry},
+    move_file, percent_encoding,
+    trash::{directory_size_at, Trash, TrashDirs},
+};
+
+/// The name of the cache file within the trash directory
+const DIRECTORY_SIZES: &str = "directorysizes";
+
+/// A line of the `$trash/directorysizes` cache file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectorySize {
+    /// The disk space used by the trashed directory, in bytes
+    pub size: u64,
+    /// The modification time of the directory's `.trashinfo` file, in seconds since the Unix epoch
+    pub mtime: u64,
+    /// The percent-encoded name of the directory in `$trash/files`
+    pub encoded_name: String,
+}
+
+impl DirectorySize {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(3, ' ');
+
+        Some(Self {
+            size: fields.next()?.parse().ok()?,
+            mtime: fields.next()?.parse().ok()?,
+            encoded_name: fields.next()?.to_owned(),
+        })
+    }
+}
+
+/// Reads the entries of the `directorysizes` file of `trash`.
+///
+/// A missing file is treated as an empty cache, and malformed lines are skipped.
+#[cfg(test)]
+pub fn read_directory_sizes(trash: &Trash) -> Result<Vec<DirectorySize>> {
+    read_entries(&Dir::open(&trash.root)?)
+}
+
+/// Reads the entries of the `directorysizes` file of the trash directory opened as `root`
+fn read_entries(root: &Dir) -> Result<Vec<DirectorySize>> {
+    let mut file = match root.open_file(OsStr::new(DIRECTORY_SIZES), libc::O_RDONLY, 0) {
+        Ok(file) => file,
+        Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    Ok(contents.lines().filter_map(DirectorySize::parse).collect())
+}
+
+/// Replaces the `directorysizes` file of the trash directory opened as `root`
+/// with the given entries.
+///
+/// The new contents are written to a temporary file which is then renamed over
+/// the previous cache, so that readers never see a partially written file.
+fn write_directory_sizes(root: &Dir, entries: &[DirectorySize]) -> Result<()> {
+    let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL;
+    let (temp_name, mut temp_file) =
+        move_file::create_staging(|name| root.open_file(name, flags, 0o600))?;
+
+    let mut write_temp = || -> Result<()> {
+        for entry in entries {
+            writeln!(
+                temp_file,
+                "{} {} {}",
+                entry.size, entry.mtime, entry.encoded_name
+            )?;
+        }
+        temp_file.sync_all()?;
+
+        root.rename(&temp_name, root, OsStr::new(DIRECTORY_SIZES))
+    };
+
+    write_temp().inspect_err(|_| {
+        let _ = root.unlink(&temp_name, false);
+    })
+}
+
+/// Applies `update` to the entries of the `directorysizes` file of the trash directory
+/// opened as `root`, writing them back if it returns `true`.
+///
+/// The trash directory stays locked meanwhile, so that concurrent updates,
+/// from other threads or processes, can't get lost.
+fn update_directory_sizes(
+    root: &Dir,
+    update: impl FnOnce(&mut Vec<DirectorySize>) -> bool,
+) -> Result<()> {
+    let _lock = root.lock()?;
+
+    let mut entries = read_entries(root)?;
+
+    if update(&mut entries) {
+        write_directory_sizes(root, &entries)?;
+    }
+
+    Ok(())
+}
+
+/// Records the size of the directory trashed as `file_name` in the `directorysizes` cache.
+pub fn add_directory_size(dirs: &TrashDirs, file_name: &OsStr) -> Result<()> {
+    let size = directory_size_at(&dirs.files, file_name)?;
+
+    let mtime = dirs.info.stat(&make_info_file_name(file_name))?.modified();
+
+    let encoded_name = percent_encoding::encode(file_name);
+
+    update_directory_sizes(&dirs.root, |entries| {
+        entries.retain(|entry| entry.encoded_name != encoded_name);
+        entries.push(DirectorySize {
+            size,
+            mtime,
+            encoded_name,
+        });
+
+        true
+    })
+}
+
+/// Removes the entry of the directory trashed as `file_name` from the `directorysizes` cache,
+/// if there is one.
+pub fn remove_directory_size(trash: &Trash, file_name: &OsStr) -> Result<()> {
+    let encoded_name = percent_encoding::encode(file_name);
+
+    update_directory_sizes(&Dir::open(&trash.root)?, |entries| {
+        let previous_len = entries.len();
+        entries.retain(|entry| entry.encoded_name != encoded_name);
+
+        entries.len() != previous_len
+    })
+}
+
+/// Records the size of the directory which was just trashed as `file_name` in the
+/// `directorysizes` cache of `trash`, opened as `dirs`.
+///
+/// The cache being only an optimisation, a failure is returned as `Error::CannotUpdateCache`
+/// for the caller to report, rather than undoing the trashing.
+pub fn record_entry(trash: &Trash, dirs: &TrashDirs, file_name: &OsStr) -> Option<Error> {
+    add_directory_size(dirs, file_name)
+        .err()
+        .map(|err| Error::CannotUpdateCache(trash.directory_sizes.clone(), Box::new(err)))
+}
+
+/// Removes the entry of `entry`, which just left the trash, from the `directorysizes` cache.
+///
+/// The cache being only an optimisation, a failure is returned as `Error::CannotUpdateCache`
+/// for the caller to report, rather than undoing what was done to the item.
+pub fn forget_entry(entry: &TrashEntry) -> Option<Error> {
+    let trash = entry.trash()?;
+
+    remove_directory_size(&trash, &entry.trashed_name)
+        .err()
+        .map(|err| Error::CannotUpdateCache(trash.directory_sizes, Box::new(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Barrier, thread, time::UNIX_EPOCH};
+
+    use crate::{
+        directory_sizes::{
+            add_directory_size, read_directory_sizes, remove_directory_size, DirectorySize,
+        },
+        ffi,
+        test::{dummy_bytes, dummy_context},
+        trash::{self, directory_size, Trash},
+    };
+
+    #[test]
+    fn test_directory_sizes_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = dummy_context(dir.path());
+        let trash = Trash::from_topdir(dir.path(), ffi::effective_user_id()).unwrap();
+
+        // Files don't get an entry
+        let file = dir.path().join("file");
+        fs::write(&file, dummy_bytes()).unwrap();
+        trash::send_to_trash(&ctx, file, &trash).unwrap();
+        assert!(read_directory_sizes(&trash).unwrap().is_empty());
+
+        let trashed_dir = dir.path().join("some dir");
+        fs::create_dir(&trashed_dir).unwrap();
+        fs::write(trashed_dir.join("dummy"), dummy_bytes()).unwrap();
+        trash::send_to_trash(&ctx, trashed_dir, &trash).unwrap();
+
+        let info_mtime = fs::metadata(trash.info.join("some dir.trashinfo"))
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let size = directory_size(trash.files.join("some dir")).unwrap();
+
+        assert_eq!(
+            read_directory_sizes(&trash).unwrap(),
+            vec![DirectorySize {
+                size,
+                mtime: info_mtime,
+                encoded_name: "some%20dir".into(),
+            }]
+        );
+        assert_eq!(
+            fs::read_to_string(&trash.directory_sizes).unwrap(),
+            format!("{} {} some%20dir\n", size, info_mtime)
+        );
+
+        remove_directory_size(&trash, "some dir".as_ref()).unwrap();
+        assert!(read_directory_sizes(&trash).unwrap().is_empty());
+
+        // No temporary file should be left behind
+        let leftovers: Vec<_> = fs::read_dir(&trash.root)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name())
+            .filter(|name| name.to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[test]
+    fn test_malformed_lines_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::new(dir.path());
+
+        fs::write(
+            &trash.directory_sizes,
+            "4096 1628000000 foo\nnot a valid line\n8192 1628000001 bar%20baz\n",
+        )
+        .unwrap();
+
+        let entries = read_directory_sizes(&trash).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| &*entry.encoded_name).collect();
+
+        assert_eq!(names, ["foo", "bar%20baz"]);
+    }
+
+    #[test]
+    fn test_concurrent_updates_are_kept() {
+        const THREADS: usize = 16;
+
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::from_topdir(dir.path(), ffi::effective_user_id()).unwrap();
+
+        for idx in 0..THREADS {
+            fs::create_dir(trash.files.join(format!("dir{}", idx))).unwrap();
+            fs::write(trash.info.join(format!("dir{}.trashinfo", idx)), b"").unwrap();
+        }
+
+        let barrier = Barrier::new(THREADS);
+        thread::scope(|scope| {
+            for idx in 0..THREADS {
+                let (trash, barrier) = (&trash, &barrier);
+                scope.spawn(move || {
+                    barrier.wait();
+                    // Every thread opens the trash on its own, as separate `tt::trash` calls do
+                    let dirs = trash.open_dirs().unwrap();
+                    add_directory_size(&dirs, format!("dir{}", idx).as_ref()).unwrap();
+                });
+            }
+        });
+
+        assert_eq!(read_directory_sizes(&trash).unwrap().len(), THREADS);
+    }
+}
@@ -0,0 +1,379 @@
+use std::{io, time::Duration};
+
+use crate::{
+    directory_sizes,
+    error::{Error, Result},
+    ffi::Dir,
+    info_file::TrashEntry,
+    list, move_file,
+    trash::Trash,
+};
+
+/// Restricts which trashed items get permanently deleted by `empty`.
+///
+/// An item is only deleted if it's selected by every filter that was given.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EmptyFilter {
+    /// Only delete items trashed longer ago than this
+    pub older_than: Option<Duration>,
+    /// Keep the newest items whose sizes add up to at most this many bytes
+    pub keep_newest_bytes: Option<u64>,
+}
+
+/// Parses an age such as `30d`, `12h` or `2w`.
+///
+/// Accepted units are `s`, `m`, `h`, `d` and `w`.
+pub fn parse_age(age: &str) -> Result<Duration> {
+    let invalid = || Error::InvalidArgument(age.into());
+
+    let unit_idx = age
+        .find(|ch: char| !ch.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (quantity, unit) = age.split_at(unit_idx);
+    let quantity: u64 = quantity.parse().map_err(|_| invalid())?;
+
+    let seconds_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    quantity
+        .checked_mul(seconds_per_unit)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// Parses a size in bytes, optionally followed by a `K`, `M`, `G` or `T` (powers of 1024) suffix.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let invalid = || Error::InvalidArgument(size.into());
+
+    let unit_idx = size
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (quantity, unit) = size.split_at(unit_idx);
+    let quantity: u64 = quantity.parse().map_err(|_| invalid())?;
+
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+
+    quantity.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+/// Selects which of the given items (along with their sizes) should be deleted,
+/// `now` being the duration since the Unix epoch.
+pub fn select_for_removal(
+    mut entries: Vec<(TrashEntry, u64)>,
+    filter: EmptyFilter,
+    now: Duration,
+) -> Vec<(TrashEntry, u64)> {
+    // Newest first
+    entries.sort_by(|(a, _), (b, _)| b.deletion_date.cmp(&a.deletion_date));
+
+    let mut kept_bytes = 0_u64;
+    let mut budget_exhausted = false;
+
+    entries
+        .into_iter()
+        .filter(|(entry, size)| {
+            let old_enough = match filter.older_than {
+                Some(age) => entry
+                    .deletion_time()
+                    .is_some_and(|deleted_at| deleted_at + age < now),
+                None => true,
+            };
+
+            let over_budget = match filter.keep_newest_bytes {
+                Some(budget) if !budget_exhausted && kept_bytes + size <= budget => {
+                    kept_bytes += size;
+                    false
+                }
+                Some(_) => {
+                    // Everything older than this item must go as well
+                    budget_exhausted = true;
+                    true
+                }
+                None => true,
+            };
+
+            old_enough && over_budget
+        })
+        .collect()
+}
+
+/// Permanently deletes a trashed item.
+///
+/// The item in `$trash/files` is removed first, and its info file afterwards,
+/// so that an interrupted removal never leaves an item without its info file.
+///
+/// Failing to update the `directorysizes` cache is returned as a warning.
+pub fn remove_entry(entry: &TrashEntry) -> Result<Vec<Error>> {
+    // Relative to the opened `$trash/files` and `$trash/info`, so that nothing outside
+    // of the trash can be removed, even if one of its ancestors gets renamed meanwhile
+    let (files_dir, trashed_name) = Dir::open_parent(&entry.trashed_path)?;
+    let (info_dir, info_name) = Dir::open_parent(&entry.info_path)?;
+
+    match move_file::remove_recursively(&files_dir, trashed_name) {
+        // An orphaned info file can still be removed
+        Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {}
+        other => other?,
+    }
+
+    info_dir.unlink(info_name, false)?;
+
+    Ok(directory_sizes::forget_entry(entry).into_iter().collect())
+}
+
+/// The outcome of `purge`
+#[derive(Debug, Default)]
+pub struct PurgeReport {
+    /// The disk space freed by the items which were removed, in bytes
+    pub reclaimed_bytes: u64,
+    /// Why the trash couldn't be fully read, then why the other selected items
+    /// couldn't be removed, as `Error::CannotPurge`
+    pub errors: Vec<Error>,
+    /// What went wrong once items were removed, as `Error::CannotUpdateCache`
+    pub warnings: Vec<Error>,
+}
+
+/// Permanently deletes the items of every given trash directory selected by `filter`,
+/// `now` being the duration since the Unix epoch.
+///
+/// Items which can't be removed are skipped, the others still being removed.
+pub fn purge(trashes: &[Trash], filter: EmptyFilter, now: Duration) -> PurgeReport {
+    let listing = list::trashed_items(trashes);
+
+    let entries = listing
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let size = entry.size().unwrap_or(0);
+            (entry, size)
+        })
+        .collect();
+
+    let mut report = PurgeReport {
+        errors: listing.errors,
+        ..Default::default()
+    };
+
+    for (entry, size) in select_for_removal(entries, filter, now) {
+        match remove_entry(&entry) {
+            Ok(warnings) => {
+                report.reclaimed_bytes += size;
+                report.warnings.extend(warnings);
+            }
+            Err(err) => report
+                .errors
+                .push(Error::CannotPurge(entry.original_path, Box::new(err))),
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::MetadataExt,
+        path::PathBuf,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{
+        directory_sizes::read_directory_sizes,
+        empty::{parse_age, parse_size, purge, remove_entry, select_for_removal, EmptyFilter},
+        error::Error,
+        ffi,
+        info_file::TrashEntry,
+        test::{dummy_bytes, dummy_context},
+        trash::{self, Trash},
+    };
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn now() -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+    }
+
+    fn entry_deleted_at(name: &str, deleted_at: Duration) -> TrashEntry {
+        TrashEntry {
+            trashed_name: name.into(),
+            trashed_path: PathBuf::from("/trash/files").join(name),
+            info_path: PathBuf::from("/trash/info").join(format!("{}.trashinfo", name)),
+            original_path: PathBuf::from("/original").join(name),
+            deletion_date: ffi::format_time(deleted_at).unwrap(),
+            topdir: None,
+        }
+    }
+
+    fn names(selected: &[(TrashEntry, u64)]) -> Vec<&str> {
+        selected
+            .iter()
+            .map(|(entry, _)| entry.trashed_name.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d").unwrap(), 30 * DAY);
+        assert_eq!(parse_age("2w").unwrap(), 14 * DAY);
+        assert_eq!(parse_age("12h").unwrap(), DAY / 2);
+        assert_eq!(parse_age("90s").unwrap(), Duration::from_secs(90));
+
+        for invalid in &["", "30", "d", "30y", "-1d", "1.5d"] {
+            assert!(parse_age(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("4K").unwrap(), 4096);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+
+        for invalid in &["", "K", "4KB", "-1"] {
+            assert!(parse_size(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_select_for_removal() {
+        let now = now();
+        let entries = vec![
+            (entry_deleted_at("new", now - DAY), 100),
+            (entry_deleted_at("old", now - 40 * DAY), 100),
+            (entry_deleted_at("older", now - 60 * DAY), 100),
+        ];
+
+        let all = select_for_removal(entries.clone(), EmptyFilter::default(), now);
+        assert_eq!(names(&all), ["new", "old", "older"]);
+
+        let older_than = EmptyFilter {
+            older_than: Some(30 * DAY),
+            ..Default::default()
+        };
+        let selected = select_for_removal(entries.clone(), older_than, now);
+        assert_eq!(names(&selected), ["old", "older"]);
+
+        let keep_newest = EmptyFilter {
+            keep_newest_bytes: Some(250),
+            ..Default::default()
+        };
+        let selected = select_for_removal(entries.clone(), keep_newest, now);
+        assert_eq!(names(&selected), ["older"]);
+
+        let both = EmptyFilter {
+            older_than: Some(50 * DAY),
+            keep_newest_bytes: Some(100),
+        };
+        let selected = select_for_removal(entries, both, now);
+        assert_eq!(names(&selected), ["older"]);
+    }
+
+    #[test]
+    fn test_remove_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::from_topdir(dir.path(), ffi::effective_user_id()).unwrap();
+
+        let trashed_dir = dir.path().join("dummy-dir");
+        fs::create_dir(&trashed_dir).unwrap();
+        fs::write(trashed_dir.join("dummy"), dummy_bytes()).unwrap();
+        trash::send_to_trash(&dummy_context(dir.path()), trashed_dir, &trash).unwrap();
+
+        let entry = trash.entries().unwrap().entries.pop().unwrap();
+        assert!(entry.trashed_path.exists());
+
+        assert_eq!(read_directory_sizes(&trash).unwrap().len(), 1);
+
+        remove_entry(&entry).unwrap();
+
+        assert!(!entry.trashed_path.exists());
+        assert!(!entry.info_path.exists());
+        assert!(read_directory_sizes(&trash).unwrap().is_empty());
+
+        // An info file whose item is gone should still be removable
+        fs::write(&entry.info_path, "[Trash Info]").unwrap();
+        remove_entry(&entry).unwrap();
+        assert!(!entry.info_path.exists());
+    }
+
+    #[test]
+    fn test_remove_entry_never_leaves_the_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::from_topdir(dir.path(), ffi::effective_user_id()).unwrap();
+
+        let trashed_dir = dir.path().join("dummy-dir");
+        fs::create_dir(&trashed_dir).unwrap();
+        trash::send_to_trash(&dummy_context(dir.path()), trashed_dir, &trash).unwrap();
+        let entry = trash.entries().unwrap().entries.pop().unwrap();
+
+        // The trashed directory gets swapped for a link to something outside of the trash
+        let outside = dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("precious"), dummy_bytes()).unwrap();
+        fs::remove_dir(&entry.trashed_path).unwrap();
+        std::os::unix::fs::symlink(&outside, &entry.trashed_path).unwrap();
+
+        remove_entry(&entry).unwrap();
+
+        assert!(fs::symlink_metadata(&entry.trashed_path).is_err());
+        assert!(outside.join("precious").exists());
+    }
+
+    #[test]
+    fn test_purge_carries_on_past_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = dummy_context(dir.path());
+        let trash = Trash::from_topdir(dir.path(), ffi::effective_user_id()).unwrap();
+
+        let mut expected_bytes = 0;
+        for name in &["a", "b"] {
+            let path = dir.path().join(name);
+            fs::write(&path, dummy_bytes()).unwrap();
+            let entry = trash::send_to_trash(&ctx, path, &trash).unwrap().entry;
+            expected_bytes += entry.size().unwrap();
+        }
+
+        // Every item is listed twice, so removing the second copy fails
+        let report = purge(
+            &[trash.clone(), trash.clone()],
+            EmptyFilter::default(),
+            now(),
+        );
+
+        assert_eq!(report.reclaimed_bytes, expected_bytes);
+        assert_eq!(report.errors.len(), 2);
+        assert!(report
+            .errors
+            .iter()
+            .all(|err| matches!(err, Error::CannotPurge(..))));
+        assert_eq!(fs::read_dir(&trash.files).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(&trash.info).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_files_and_directories_are_measured_alike() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = dummy_context(dir.path());
+        let trash = Trash::from_topdir(dir.path(), ffi::effective_user_id()).unwrap();
+
+        let file = dir.path().join("file");
+        fs::write(&file, b"tiny").unwrap();
+        let entry = trash::send_to_trash(&ctx, file, &trash).unwrap().entry;
+
+        // Disk usage, as for directories, rather than the length of the file
+        let metadata = fs::metadata(&entry.trashed_path).unwrap();
+        assert_eq!(entry.size().unwrap(), metadata.blocks() * 512);
+    }
+}
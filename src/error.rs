@@ -1,21 +1,147 @@
-use std::{ffi::NulError, path::PathBuf};
+use std::{
+    ffi::NulError,
+    io,
+    path::{Path, PathBuf},
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("IO: `{0}`")]
-    Io(#[from] std::io::Error),
-    #[error("Failed to obtain filename for {0}")]
+    #[error("{}", describe_io_error(.0))]
+    Io(#[from] io::Error),
+    #[error("it has no file name")]
     FailedToObtainFileName(PathBuf),
-    #[error("UTF8 error: {0}")]
+    #[error("the path contains a nul byte")]
     InternalNulByte(#[from] NulError),
-    #[error("Failed to obtain mount points")]
+    #[error("the user's home directory was not found")]
+    HomeDirNotFound,
+    #[error("the mount points could not be read")]
     FailedToObtainMountPoints,
-    #[error("A directory was expected but {0} isn't one")]
+    #[error("'{}' is not a directory", .0.display())]
     NotADirectory(PathBuf),
-    #[error("UnixString error: {0}")]
+    #[error("invalid path: {0}")]
     UnixString(#[from] unixstring::Error),
-    #[error("The mount point of {0} was not found: {0}")]
+    #[error("its mount point was not found")]
     MountPointNotFound(PathBuf),
+    #[error("the shared trash directory '{}' can't be used: {1}", .0.display())]
+    InvalidSharedTrash(PathBuf, InvalidSharedTrash),
+    #[error("the trash directory '{}' can't be used: {1}", .0.display())]
+    InvalidTrashDirectory(PathBuf, InvalidDirectory),
+    #[error("'{}' is not a valid .trashinfo file", .0.display())]
+    InvalidInfoFile(PathBuf),
+    #[error("'{}' is not in the trash", .0.display())]
+    NotInTrash(PathBuf),
+    #[error("its original path is taken (use --rename to restore it under another name)")]
+    RestoreTargetExists(PathBuf),
+    #[error("invalid selection: `{0}`")]
+    InvalidSelection(String),
+    #[error("invalid argument: `{0}`")]
+    InvalidArgument(String),
+    #[error("missing operand")]
+    MissingOperand,
+    #[error("refusing to empty the whole trash without --all")]
+    UnfilteredPurge,
+    #[error("cannot trash '{}': {1}", .0.display())]
+    CannotTrash(PathBuf, Box<Error>),
+    #[error("cannot restore '{}': {1}", .0.display())]
+    CannotRestore(PathBuf, Box<Error>),
+    #[error("cannot remove '{}' from the trash: {1}", .0.display())]
+    CannotPurge(PathBuf, Box<Error>),
+    #[error("cannot read '{}': {1}", .0.display())]
+    CannotList(PathBuf, Box<Error>),
+    #[error("cannot update '{}': {1}", .0.display())]
+    CannotUpdateCache(PathBuf, Box<Error>),
+    #[error("{1} (use --force-dangerous to override)")]
+    DangerousTarget(PathBuf, DangerousTarget),
+}
+
+impl Error {
+    /// The path of the file this error is about, if any.
+    ///
+    /// Errors returned by `tt::trash` always have one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::FailedToObtainFileName(path)
+            | Error::NotADirectory(path)
+            | Error::MountPointNotFound(path)
+            | Error::InvalidSharedTrash(path, _)
+            | Error::InvalidTrashDirectory(path, _)
+            | Error::InvalidInfoFile(path)
+            | Error::NotInTrash(path)
+            | Error::RestoreTargetExists(path)
+            | Error::CannotTrash(path, _)
+            | Error::CannotRestore(path, _)
+            | Error::CannotPurge(path, _)
+            | Error::CannotList(path, _)
+            | Error::CannotUpdateCache(path, _)
+            | Error::DangerousTarget(path, _) => Some(path),
+            Error::Io(_)
+            | Error::InternalNulByte(_)
+            | Error::HomeDirNotFound
+            | Error::FailedToObtainMountPoints
+            | Error::UnixString(_)
+            | Error::InvalidSelection(_)
+            | Error::InvalidArgument(_)
+            | Error::MissingOperand
+            | Error::UnfilteredPurge => None,
+        }
+    }
+}
+
+/// Describes an IO error the way command-line tools do, e.g. `permission denied`
+fn describe_io_error(err: &io::Error) -> String {
+    let description = err.to_string();
+
+    // Drop the ` (os error 13)` suffix std adds
+    let description = match (err.raw_os_error(), description.rfind(" (os error ")) {
+        (Some(_), Some(idx)) => &description[..idx],
+        _ => &description,
+    };
+
+    let mut chars = description.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => description.into(),
+    }
+}
+
+/// The reasons for which an administrator-provided `$topdir/.Trash` must not be used
+#[derive(thiserror::Error, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InvalidSharedTrash {
+    #[error("its sticky bit is not set")]
+    NotSticky,
+    #[error("it is a symbolic link")]
+    IsSymlink,
+    #[error("it is not a directory")]
+    NotADirectory,
+    #[error("it is not a directory owned by the user")]
+    WrongOwner,
+}
+
+/// The reasons for which a trash directory, or one of its `files` and `info` subdirectories,
+/// must not be used
+#[derive(thiserror::Error, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InvalidDirectory {
+    #[error("it is a symbolic link")]
+    IsSymlink,
+    #[error("it is not a directory")]
+    NotADirectory,
+    #[error("it is not owned by the user")]
+    WrongOwner,
+}
+
+/// The reasons for which a file is refused by `tt::trash` unless forced
+#[derive(thiserror::Error, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DangerousTarget {
+    #[error("it is the filesystem root")]
+    Root,
+    #[error("it is the home directory")]
+    HomeDir,
+    #[error("it is a mount point")]
+    MountPoint,
+    #[error("it is or holds a trash directory")]
+    TrashDir,
+    #[error("it is `.` or `..`")]
+    CurrentOrParentDir,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
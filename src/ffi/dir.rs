@@ -0,0 +1,463 @@
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+
+use libc::{c_int, AT_REMOVEDIR, AT_SYMLINK_NOFOLLOW};
+use unixstring::UnixString;
+
+use crate::error::{Error, Result};
+use crate::ffi::{rename::rename_noreplace_at, Lstat};
+
+/// Turns the `-1` returned by a failed libc call into an error.
+fn check(ret: c_int) -> Result<c_int> {
+    if ret == -1 {
+        let io_err = io::Error::last_os_error();
+        Err(Error::Io(io_err))
+    } else {
+        Ok(ret)
+    }
+}
+
+fn to_cstring(name: &OsStr) -> Result<CString> {
+    Ok(CString::new(name.as_bytes())?)
+}
+
+/// An open directory.
+///
+/// Files are operated on by name relative to it (through `openat(2)` and friends),
+/// so that a concurrent rename of the directory or of any of its ancestors can't
+/// redirect those operations elsewhere. Symbolic links are never followed.
+#[derive(Debug)]
+pub struct Dir {
+    fd: OwnedFd,
+}
+
+impl Dir {
+    /// Opens the directory given by `path`.
+    ///
+    /// Symbolic links within `path` are followed, as they would for any other path.
+    pub fn open(path: &Path) -> Result<Self> {
+        let path = UnixString::try_from(path.to_owned())?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+
+        // Safety: `path` is a valid NUL-terminated string
+        let fd = check(unsafe { libc::open(path.as_ptr(), flags) })?;
+
+        // Safety: `fd` was just opened and isn't owned by anything else
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Opens the parent directory of `path`, returning it along with the file name of `path`.
+    ///
+    /// A bare file name is taken to be relative to the current directory.
+    pub fn open_parent(path: &Path) -> Result<(Self, &OsStr)> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| Error::FailedToObtainFileName(path.to_owned()))?;
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        Ok((Self::open(parent)?, file_name))
+    }
+
+    /// Opens the directory `name` within this one.
+    /// Fails with `ELOOP` or `ENOTDIR` if `name` is a symbolic link.
+    pub fn open_dir(&self, name: &OsStr) -> Result<Self> {
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY;
+        let fd = self.open_raw(name, flags, 0)?;
+
+        Ok(Self { fd })
+    }
+
+    /// Opens the file `name` within this one, with the given `open(2)` flags,
+    /// `mode` being used if it gets created. Symbolic links are never followed.
+    pub fn open_file(&self, name: &OsStr, flags: c_int, mode: u32) -> Result<File> {
+        Ok(File::from(self.open_raw(name, flags, mode)?))
+    }
+
+    fn open_raw(&self, name: &OsStr, flags: c_int, mode: u32) -> Result<OwnedFd> {
+        let name = to_cstring(name)?;
+        let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+
+        // Safety: `name` is a valid NUL-terminated string
+        let fd = check(unsafe { libc::openat(self.raw(), name.as_ptr(), flags, mode) })?;
+
+        // Safety: `fd` was just opened and isn't owned by anything else
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// The status of the file `name`, without following symbolic links.
+    pub fn stat(&self, name: &OsStr) -> Result<Lstat> {
+        let name = to_cstring(name)?;
+
+        // Safety: The all-zero byte-pattern is a valid `struct stat`
+        let mut stat_buf = unsafe { mem::zeroed() };
+
+        // Safety: `name` is a valid NUL-terminated string and `stat_buf` a valid `struct stat`
+        check(unsafe {
+            libc::fstatat(
+                self.raw(),
+                name.as_ptr(),
+                &mut stat_buf,
+                AT_SYMLINK_NOFOLLOW,
+            )
+        })?;
+
+        Ok(Lstat::from_raw(stat_buf))
+    }
+
+    /// Renames `name` to `to_name` within `to_dir`, failing with `EEXIST`
+    /// instead of replacing anything already there.
+    pub fn rename_noreplace(&self, name: &OsStr, to_dir: &Dir, to_name: &OsStr) -> Result<()> {
+        rename_noreplace_at(
+            self.raw(),
+            &to_cstring(name)?,
+            to_dir.raw(),
+            &to_cstring(to_name)?,
+        )
+    }
+
+    /// Renames `name` to `to_name` within `to_dir`, atomically replacing whatever is there.
+    pub fn rename(&self, name: &OsStr, to_dir: &Dir, to_name: &OsStr) -> Result<()> {
+        let name = to_cstring(name)?;
+        let to_name = to_cstring(to_name)?;
+
+        // Safety: `name` and `to_name` are valid NUL-terminated strings
+        check(unsafe {
+            libc::renameat(self.raw(), name.as_ptr(), to_dir.raw(), to_name.as_ptr())
+        })?;
+
+        Ok(())
+    }
+
+    /// Takes an exclusive `flock(2)` lock on the directory, waiting for it if needed.
+    ///
+    /// The lock is released when the returned guard is dropped. Every `Dir::open`
+    /// of a directory locks on its own, so this also excludes other threads of the process.
+    pub fn lock(&self) -> Result<DirLock<'_>> {
+        // Safety: `self.fd` is a valid descriptor
+        check(unsafe { libc::flock(self.raw(), libc::LOCK_EX) })?;
+
+        Ok(DirLock { dir: self })
+    }
+
+    /// Removes the file `name`, which must be an empty directory if `is_dir` is set.
+    pub fn unlink(&self, name: &OsStr, is_dir: bool) -> Result<()> {
+        let name = to_cstring(name)?;
+        let flags = if is_dir { AT_REMOVEDIR } else { 0 };
+
+        // Safety: `name` is a valid NUL-terminated string
+        check(unsafe { libc::unlinkat(self.raw(), name.as_ptr(), flags) })?;
+
+        Ok(())
+    }
+
+    /// Creates the directory `name`, with the given permission bits.
+    pub fn create_dir(&self, name: &OsStr, mode: u32) -> Result<()> {
+        let name = to_cstring(name)?;
+
+        // Safety: `name` is a valid NUL-terminated string
+        check(unsafe { libc::mkdirat(self.raw(), name.as_ptr(), mode as libc::mode_t) })?;
+
+        Ok(())
+    }
+
+    /// Creates the symbolic link `name`, pointing to `target`.
+    pub fn symlink(&self, target: &OsStr, name: &OsStr) -> Result<()> {
+        let (target, name) = (to_cstring(target)?, to_cstring(name)?);
+
+        // Safety: both strings are valid and NUL-terminated
+        check(unsafe { libc::symlinkat(target.as_ptr(), self.raw(), name.as_ptr()) })?;
+
+        Ok(())
+    }
+
+    /// The target of the symbolic link `name`.
+    pub fn read_link(&self, name: &OsStr) -> Result<OsString> {
+        let name = to_cstring(name)?;
+        let mut buf = vec![0_u8; 256];
+
+        loop {
+            // Safety: `buf` is valid for `buf.len()` bytes
+            let written = unsafe {
+                libc::readlinkat(
+                    self.raw(),
+                    name.as_ptr(),
+                    buf.as_mut_ptr() as *mut libc::c_char,
+                    buf.len(),
+                )
+            };
+
+            if written == -1 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+
+            // The target may have been truncated if it filled the whole buffer
+            if (written as usize) < buf.len() {
+                buf.truncate(written as usize);
+                return Ok(OsString::from_vec(buf));
+            }
+
+            buf.resize(buf.len() * 2, 0);
+        }
+    }
+
+    /// Creates the special file (FIFO, socket, character or block device) `name`.
+    ///
+    /// `mode` holds both the file type and the permission bits, as in `st_mode`,
+    /// and `device` is only meaningful for device nodes.
+    pub fn mknod(&self, name: &OsStr, mode: u32, device: u64) -> Result<()> {
+        let name = to_cstring(name)?;
+
+        // Safety: `name` is a valid NUL-terminated string
+        check(unsafe {
+            libc::mknodat(
+                self.raw(),
+                name.as_ptr(),
+                mode as libc::mode_t,
+                device as libc::dev_t,
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// Changes the owner and group of the file `name`, without following symbolic links.
+    pub fn chown(&self, name: &OsStr, uid: u32, gid: u32) -> Result<()> {
+        let name = to_cstring(name)?;
+
+        // Safety: `name` is a valid NUL-terminated string
+        check(unsafe { libc::fchownat(self.raw(), name.as_ptr(), uid, gid, AT_SYMLINK_NOFOLLOW) })?;
+
+        Ok(())
+    }
+
+    /// Changes the permission bits of the file `name`, which must not be a symbolic link.
+    pub fn chmod(&self, name: &OsStr, mode: u32) -> Result<()> {
+        let name = to_cstring(name)?;
+
+        // Safety: `name` is a valid NUL-terminated string.
+        // Linux doesn't support `AT_SYMLINK_NOFOLLOW` here, links never have permissions anyway
+        check(unsafe { libc::fchmodat(self.raw(), name.as_ptr(), mode as libc::mode_t, 0) })?;
+
+        Ok(())
+    }
+
+    /// Sets the access and modification times of the file `name`, with nanosecond precision.
+    /// Symbolic links are not followed.
+    ///
+    /// Both times are given as `(seconds, nanoseconds)` since the Unix epoch.
+    pub fn set_times(
+        &self,
+        name: &OsStr,
+        accessed: (i64, i64),
+        modified: (i64, i64),
+    ) -> Result<()> {
+        let name = to_cstring(name)?;
+        let to_timespec = |(secs, nsecs): (i64, i64)| libc::timespec {
+            tv_sec: secs as _,
+            tv_nsec: nsecs as _,
+        };
+        let times = [to_timespec(accessed), to_timespec(modified)];
+
+        // Safety: `times` holds exactly the two timespecs utimensat expects
+        check(unsafe {
+            libc::utimensat(
+                self.raw(),
+                name.as_ptr(),
+                times.as_ptr(),
+                AT_SYMLINK_NOFOLLOW,
+            )
+        })?;
+
+        Ok(())
+    }
+
+    /// The names of the files within this directory, except for `.` and `..`.
+    pub fn entries(&self) -> Result<Vec<OsString>> {
+        // fdopendir takes ownership of the descriptor it's given, so it gets a copy
+        // Safety: `self.fd` is a valid descriptor
+        let fd = check(unsafe { libc::fcntl(self.raw(), libc::F_DUPFD_CLOEXEC, 0) })?;
+
+        // Safety: `fd` is a valid directory descriptor owned by nobody else
+        let dir_stream = unsafe { libc::fdopendir(fd) };
+        if dir_stream.is_null() {
+            let io_err = io::Error::last_os_error();
+            // Safety: fdopendir failed, so `fd` is still ours
+            unsafe { libc::close(fd) };
+            return Err(Error::Io(io_err));
+        }
+
+        // The copy shares its offset with `self.fd`, which may have been read before
+        // Safety: `dir_stream` is a valid directory stream
+        unsafe { libc::rewinddir(dir_stream) };
+
+        let mut entries = Vec::new();
+        let result = loop {
+            // readdir signals errors only through errno
+            // Safety: `__errno_location` always returns a valid pointer
+            unsafe { *libc::__errno_location() = 0 };
+
+            // Safety: `dir_stream` is a valid directory stream
+            let entry = unsafe { libc::readdir(dir_stream) };
+            if entry.is_null() {
+                let io_err = io::Error::last_os_error();
+                break match io_err.raw_os_error() {
+                    Some(0) => Ok(()),
+                    _ => Err(Error::Io(io_err)),
+                };
+            }
+
+            // Safety: `d_name` is a NUL-terminated string within a valid `dirent`
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+            if name != b"." && name != b".." {
+                entries.push(OsString::from_vec(name.to_vec()));
+            }
+        };
+
+        // Safety: `dir_stream` is valid and not used after this
+        unsafe { libc::closedir(dir_stream) };
+
+        result.map(|()| entries)
+    }
+
+    /// Flushes the directory's own entries to disk.
+    pub fn sync(&self) -> Result<()> {
+        // Safety: `self.fd` is a valid descriptor
+        check(unsafe { libc::fsync(self.raw()) })?;
+
+        Ok(())
+    }
+
+    /// A path to the file `name` which goes through the descriptor of this directory
+    /// rather than through its own path, for the calls lacking an `*at` variant.
+    pub fn proc_path(&self, name: &OsStr) -> Result<UnixString> {
+        let mut path = OsString::from(format!("/proc/self/fd/{}/", self.raw()));
+        path.push(name);
+
+        Ok(UnixString::try_from(path)?)
+    }
+
+    fn raw(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// An exclusive lock on a `Dir`, released when dropped
+#[derive(Debug)]
+pub struct DirLock<'a> {
+    dir: &'a Dir,
+}
+
+impl Drop for DirLock<'_> {
+    fn drop(&mut self) {
+        // Safety: `self.dir.fd` is a valid descriptor
+        unsafe { libc::flock(self.dir.raw(), libc::LOCK_UN) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, fs, io::Write, os::unix::fs::symlink};
+
+    use crate::{error::Error, ffi::Dir};
+
+    #[test]
+    fn test_dir_operations() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(temp.path()).unwrap();
+        let name = OsStr::new;
+
+        let mut file = dir
+            .open_file(
+                name("file"),
+                libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+                0o600,
+            )
+            .unwrap();
+        file.write_all(b"dummy").unwrap();
+        assert_eq!(fs::read(temp.path().join("file")).unwrap(), b"dummy");
+
+        // O_EXCL
+        assert!(dir
+            .open_file(
+                name("file"),
+                libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+                0o600
+            )
+            .is_err());
+
+        dir.create_dir(name("sub"), 0o700).unwrap();
+        let sub = dir.open_dir(name("sub")).unwrap();
+        assert!(dir.stat(name("sub")).unwrap().is_dir());
+
+        dir.rename_noreplace(name("file"), &sub, name("moved"))
+            .unwrap();
+        assert_eq!(sub.entries().unwrap(), [name("moved")]);
+
+        // Nothing gets replaced
+        dir.open_file(name("file"), libc::O_WRONLY | libc::O_CREAT, 0o600)
+            .unwrap();
+        let err = dir
+            .rename_noreplace(name("file"), &sub, name("moved"))
+            .unwrap_err();
+        assert!(matches!(err, Error::Io(err) if err.raw_os_error() == Some(libc::EEXIST)));
+        assert_eq!(fs::read(temp.path().join("sub/moved")).unwrap(), b"dummy");
+        dir.unlink(name("file"), false).unwrap();
+
+        let long_target = "x".repeat(1000);
+        dir.symlink(name(&long_target), name("link")).unwrap();
+        assert!(dir.stat(name("link")).unwrap().is_symlink());
+        assert_eq!(dir.read_link(name("link")).unwrap(), name(&long_target));
+
+        let mut entries = dir.entries().unwrap();
+        entries.sort();
+        assert_eq!(entries, [name("link"), name("sub")]);
+
+        dir.unlink(name("link"), false).unwrap();
+        sub.unlink(name("moved"), false).unwrap();
+        dir.unlink(name("sub"), true).unwrap();
+        assert!(dir.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dir_never_follows_symlinks() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir(temp.path().join("target")).unwrap();
+        fs::write(temp.path().join("target/file"), "dummy").unwrap();
+        symlink("target", temp.path().join("link")).unwrap();
+        symlink("target/file", temp.path().join("file-link")).unwrap();
+
+        let dir = Dir::open(temp.path()).unwrap();
+
+        assert!(dir.open_dir(OsStr::new("link")).is_err());
+        assert!(dir
+            .open_file(OsStr::new("file-link"), libc::O_RDONLY, 0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_dir_survives_renames() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir(temp.path().join("before")).unwrap();
+
+        let dir = Dir::open(&temp.path().join("before")).unwrap();
+        fs::rename(temp.path().join("before"), temp.path().join("after")).unwrap();
+        // Something else takes the old name
+        fs::create_dir(temp.path().join("before")).unwrap();
+
+        dir.create_dir(OsStr::new("dummy"), 0o700).unwrap();
+        assert!(temp.path().join("after/dummy").is_dir());
+        assert!(!temp.path().join("before/dummy").exists());
+    }
+}
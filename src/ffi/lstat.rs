@@ -12,6 +12,7 @@ pub struct Lstat {
 }
 
 #[allow(dead_code)]
+#[allow(clippy::self_named_constructors)]
 impl Lstat {
     pub fn lstat(path: &UnixString) -> Result<Self> {
         Ok(Self {
@@ -19,6 +20,11 @@ impl Lstat {
         })
     }
 
+    /// Wraps a `struct stat` filled in by some other call, such as fstatat(2)
+    pub(super) const fn from_raw(inner: libc::stat) -> Self {
+        Self { inner }
+    }
+
     pub const fn mode(&self) -> u32 {
         self.inner.st_mode
     }
@@ -47,6 +53,26 @@ impl Lstat {
         self.inner.st_mtime as u64
     }
 
+    /// The nanoseconds part of the time of last access
+    pub const fn accessed_nsec(&self) -> i64 {
+        self.inner.st_atime_nsec
+    }
+
+    /// The nanoseconds part of the time of last modification
+    pub const fn modified_nsec(&self) -> i64 {
+        self.inner.st_mtime_nsec
+    }
+
+    /// The device ID of the file itself, for character and block devices
+    pub const fn raw_device_id(&self) -> u64 {
+        self.inner.st_rdev
+    }
+
+    /// The ID of the device containing the file
+    pub const fn device_id(&self) -> u64 {
+        self.inner.st_dev
+    }
+
     pub const fn owner_user_id(&self) -> u32 {
         self.inner.st_uid
     }
@@ -54,6 +80,53 @@ impl Lstat {
     pub const fn owner_group_id(&self) -> u32 {
         self.inner.st_gid
     }
+
+    pub const fn is_symlink(&self) -> bool {
+        self.mode() & libc::S_IFMT == libc::S_IFLNK
+    }
+
+    pub const fn is_dir(&self) -> bool {
+        self.mode() & libc::S_IFMT == libc::S_IFDIR
+    }
+
+    /// Whether or not the sticky bit (`S_ISVTX`) is set
+    pub const fn is_sticky(&self) -> bool {
+        self.mode() & libc::S_ISVTX != 0
+    }
+}
+
+/// The ID of the mount the file given by `path` resides in, as listed in
+/// `/proc/self/mountinfo`, or `None` if the kernel can't tell (before Linux 5.8).
+/// Symbolic links are not followed.
+///
+/// Unlike the device ID, this tells apart the bind mounts of a same filesystem,
+/// between which rename(2) fails with `EXDEV`.
+pub fn mount_id(path: &UnixString) -> Result<Option<u64>> {
+    // Safety: The all-zero byte-pattern is a valid `struct statx`
+    let mut statx_buf: libc::statx = unsafe { mem::zeroed() };
+
+    let ret = unsafe {
+        libc::statx(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+            libc::STATX_MNT_ID,
+            &mut statx_buf,
+        )
+    };
+
+    if ret == -1 {
+        let io_err = std::io::Error::last_os_error();
+
+        // statx(2) itself is missing, or filtered out by seccomp
+        if io_err.raw_os_error() == Some(libc::ENOSYS) {
+            return Ok(None);
+        }
+
+        return Err(Error::Io(io_err));
+    }
+
+    Ok(Some(statx_buf.stx_mnt_id).filter(|_| statx_buf.stx_mask & libc::STATX_MNT_ID != 0))
 }
 
 fn _lstat(path: &UnixString) -> Result<libc::stat> {
@@ -70,12 +143,12 @@ fn _lstat(path: &UnixString) -> Result<libc::stat> {
 
 #[cfg(test)]
 mod tests {
-    use std::{convert::TryFrom, time::UNIX_EPOCH};
+    use std::{convert::TryFrom, fs, os::unix::fs::PermissionsExt, time::UNIX_EPOCH};
 
     use tempfile::NamedTempFile;
     use unixstring::UnixString;
 
-    use super::Lstat;
+    use super::{mount_id, Lstat};
 
     #[test]
     fn permissions() {
@@ -105,4 +178,63 @@ mod tests {
 
         assert_eq!(mod_timestamp, stat.modified());
     }
+
+    #[test]
+    fn nanosecond_timestamps() {
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path();
+        let modified = path
+            .metadata()
+            .unwrap()
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap();
+
+        let unx = UnixString::try_from(path.to_owned()).unwrap();
+        let stat = Lstat::lstat(&unx).unwrap();
+
+        assert_eq!(modified.subsec_nanos() as i64, stat.modified_nsec());
+    }
+
+    #[test]
+    fn mount_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, b"").unwrap();
+
+        let id = |path: &std::path::Path| mount_id(&UnixString::try_from(path.to_owned()).unwrap());
+
+        let dir_id = id(dir.path()).unwrap();
+        assert_eq!(id(&file).unwrap(), dir_id);
+
+        // procfs is always a mount of its own
+        if let Some(dir_id) = dir_id {
+            assert_ne!(id("/proc/self".as_ref()).unwrap(), Some(dir_id));
+        }
+        assert!(id(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn file_type_and_sticky_bit() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path();
+
+        let link_path = dir_path.join("link");
+        std::os::unix::fs::symlink(dir_path, &link_path).unwrap();
+
+        let dir_stat = Lstat::lstat(&UnixString::try_from(dir_path.to_owned()).unwrap()).unwrap();
+        assert!(dir_stat.is_dir());
+        assert!(!dir_stat.is_symlink());
+        assert!(!dir_stat.is_sticky());
+
+        // lstat(2) must not follow the link
+        let link_stat = Lstat::lstat(&UnixString::try_from(link_path).unwrap()).unwrap();
+        assert!(link_stat.is_symlink());
+        assert!(!link_stat.is_dir());
+
+        fs::set_permissions(dir_path, fs::Permissions::from_mode(0o1777)).unwrap();
+        let dir_stat = Lstat::lstat(&UnixString::try_from(dir_path.to_owned()).unwrap()).unwrap();
+        assert!(dir_stat.is_sticky());
+    }
 }
@@ -1,11 +1,16 @@
+mod dir;
 mod home;
 mod lstat;
 mod mount_points;
+mod rename;
 mod time_fmt;
 mod user;
+mod xattr;
 
+pub use dir::Dir;
 pub use home::get_home_dir;
-pub use lstat::Lstat;
+pub use lstat::{mount_id, Lstat};
 pub use mount_points::{probe_mount_points, MountPoint};
-pub use time_fmt::format_time;
+pub use time_fmt::{format_time, parse_time};
 pub use user::effective_user_id;
+pub use xattr::{get_xattr, list_xattrs, set_xattr};
@@ -1,19 +1,38 @@
 use std::{
-    cmp::Reverse,
-    collections::BinaryHeap,
-    ffi::CStr,
+    ffi::OsString,
+    fs,
+    os::unix::ffi::OsStringExt,
     path::{Path, PathBuf},
 };
 
 use crate::error::{Error, Result};
-use cstr::cstr;
-use libc::{getmntent, setmntent};
-use unixstring::UnixString;
 
-#[derive(Debug, PartialEq, Eq)]
+/// A mount point, as described by a line of `/proc/self/mountinfo`.
+///
+/// Ref.: proc(5)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct MountPoint {
+    /// The mount source, such as `/dev/sda2`
     pub fs_name: String,
+    /// Where the filesystem is mounted
     pub fs_path_prefix: PathBuf,
+    /// A unique ID for the mount
+    pub mount_id: u32,
+    /// The ID of the parent mount
+    pub parent_id: u32,
+    /// The major device ID of the filesystem (the `st_dev` of files within it)
+    pub major: u32,
+    /// The minor device ID of the filesystem (the `st_dev` of files within it)
+    pub minor: u32,
+    /// The directory within the filesystem which forms the root of this mount.
+    /// Differs from `/` for bind mounts.
+    pub root: PathBuf,
+    /// The filesystem type, such as `ext4`
+    pub fs_type: String,
+    /// Per-mount options, such as `rw,noatime`
+    pub mount_options: String,
+    /// Per-superblock options
+    pub super_options: String,
 }
 
 #[allow(dead_code)]
@@ -22,13 +41,85 @@ impl MountPoint {
         self.fs_path_prefix == Path::new("/")
     }
 
-    pub fn is_home(&self) -> bool {
-        self.fs_path_prefix == Path::new("/home")
-    }
-
     pub fn contains(&self, path: &Path) -> bool {
         path.starts_with(&self.fs_path_prefix)
     }
+
+    /// Parses a line of `/proc/self/mountinfo`, such as:
+    ///
+    /// ```text
+    /// 36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+    /// ```
+    ///
+    /// The (zero or more) optional fields are terminated by a single hyphen.
+    pub fn from_mountinfo_line(line: &[u8]) -> Option<Self> {
+        let mut fields = line
+            .split(|&byte| byte == b' ')
+            .filter(|field| !field.is_empty());
+
+        let mut next_str = || std::str::from_utf8(fields.next()?).ok();
+
+        let mount_id = next_str()?.parse().ok()?;
+        let parent_id = next_str()?.parse().ok()?;
+        let (major, minor) = next_str()?.split_once(':')?;
+        let (major, minor) = (major.parse().ok()?, minor.parse().ok()?);
+
+        let root = unescape(fields.next()?).into();
+        let fs_path_prefix = unescape(fields.next()?).into();
+        let mount_options = lossy(fields.next()?);
+
+        // Skip the optional fields
+        fields.find(|&field| field == b"-")?;
+
+        let fs_type = lossy(fields.next()?);
+        let fs_name = lossy(fields.next()?);
+        let super_options = fields.next().map(lossy).unwrap_or_default();
+
+        Some(Self {
+            fs_name,
+            fs_path_prefix,
+            mount_id,
+            parent_id,
+            major,
+            minor,
+            root,
+            fs_type,
+            mount_options,
+            super_options,
+        })
+    }
+}
+
+fn lossy(field: &[u8]) -> String {
+    String::from_utf8_lossy(&unescape(field).into_vec()).into()
+}
+
+/// Reverts the octal escaping (e.g. `\040` for a space) that the kernel applies
+/// to spaces, tabs, newlines and backslashes in mountinfo fields.
+fn unescape(field: &[u8]) -> OsString {
+    let mut unescaped = Vec::with_capacity(field.len());
+    let mut idx = 0;
+
+    while idx < field.len() {
+        let escaped = field
+            .get(idx + 1..idx + 4)
+            .filter(|_| field[idx] == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+
+        match escaped {
+            Some(byte) => {
+                unescaped.push(byte);
+                idx += 4;
+            }
+            None => {
+                unescaped.push(field[idx]);
+                idx += 1;
+            }
+        }
+    }
+
+    OsString::from_vec(unescaped)
 }
 
 #[cfg(test)]
@@ -40,6 +131,7 @@ mod mount_point_fns {
         MountPoint {
             fs_name: "/dev/sda2".into(),
             fs_path_prefix: "/".into(),
+            ..Default::default()
         }
     }
 
@@ -47,6 +139,7 @@ mod mount_point_fns {
         MountPoint {
             fs_name: "/dev/sda2".into(),
             fs_path_prefix: "/home".into(),
+            ..Default::default()
         }
     }
 
@@ -55,12 +148,6 @@ mod mount_point_fns {
         assert!(root().is_root());
         assert!(!home().is_root());
     }
-
-    #[test]
-    fn is_home() {
-        assert!(!root().is_home());
-        assert!(home().is_home());
-    }
 }
 
 impl PartialOrd for MountPoint {
@@ -79,124 +166,117 @@ impl Ord for MountPoint {
 }
 
 pub fn probe_mount_points() -> Result<Vec<MountPoint>> {
-    let path = cstr!("/etc/mtab");
-
-    probe_mount_points_in(path)
+    probe_mount_points_in(Path::new("/proc/self/mountinfo"))
 }
 
-pub fn probe_mount_points_in(path: &CStr) -> Result<Vec<MountPoint>> {
-    let mut mount_points = BinaryHeap::new();
-
-    let read_arg = cstr!("r");
-    let file = unsafe { setmntent(path.as_ptr(), read_arg.as_ptr()) };
-
-    if file.is_null() {
-        return Err(Error::FailedToObtainMountPoints);
-    }
-
-    loop {
-        let entry = unsafe { getmntent(file) };
-        if entry.is_null() {
-            break;
-        }
-        // We just made sure `entry` is not null,
-        // so this deref must be safe (I guess?)
-        let fs_name = unsafe { (*entry).mnt_fsname };
-        let fs_dir = unsafe { (*entry).mnt_dir };
-
-        let fs_name = unsafe { UnixString::from_ptr(fs_name) };
-
-        let fs_dir = unsafe { UnixString::from_ptr(fs_dir) };
-
-        let mount_point = MountPoint {
-            fs_name: fs_name.to_string_lossy().into(),
-            fs_path_prefix: fs_dir.into(),
-        };
-        mount_points.push(Reverse(mount_point));
-    }
-
-    Ok(mount_points
-        .into_sorted_vec()
-        .into_iter()
-        .map(|rev_mount_point| rev_mount_point.0)
-        .collect())
+/// Reads the mount points listed in the mountinfo file given by `path`.
+///
+/// The mount points are sorted by decreasing path length, so that the first
+/// one containing a given path is the one that path resides in.
+/// When mounts are stacked on the same path, the most recent one comes first.
+pub fn probe_mount_points_in(path: &Path) -> Result<Vec<MountPoint>> {
+    let contents = fs::read(path).map_err(|_| Error::FailedToObtainMountPoints)?;
+
+    let mut mount_points: Vec<_> = contents
+        .split(|&byte| byte == b'\n')
+        .filter_map(MountPoint::from_mountinfo_line)
+        .collect();
+
+    // Later lines shadow earlier ones mounted on the same path,
+    // and the sort below is stable
+    mount_points.reverse();
+    mount_points.sort_by(|a, b| b.cmp(a));
+
+    Ok(mount_points)
 }
 
 #[cfg(test)]
 mod mount_point_probing_tests {
     use tempfile::NamedTempFile;
 
-    use std::{collections::BTreeSet, ffi::CString, io::Write, os::unix::prelude::OsStrExt};
+    use std::{collections::BTreeSet, io::Write, path::PathBuf};
 
     use crate::ffi::mount_points::{probe_mount_points_in, MountPoint};
 
-    const TEST_MTAB: &str = r#"
-    proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
-    sys /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
-    dev /dev devtmpfs rw,nosuid,relatime,size=10574240k,nr_inodes=5743635,mode=755,inode64 0 0
-    run /run tmpfs rw,nosuid,nodev,relatime,mode=755,inode64 0 0
-    efivarfs /sys/firmware/efi/efivars efivarfs rw,nosuid,nodev,noexec,relatime 0 0
-    /dev/sda2 / ext4 rw,noatime 0 0
-    securityfs /sys/kernel/security securityfs rw,nosuid,nodev,noexec,relatime 0 0
-    tmpfs /dev/shm tmpfs rw,nosuid,nodev,inode64 0 0
-    devpts /dev/pts devpts rw,nosuid,noexec,relatime,gid=5,mode=620,ptmxmode=000 0 0
+    const TEST_MOUNTINFO: &str = r#"23 28 0:22 / /proc rw,nosuid,nodev,noexec,relatime shared:5 - proc proc rw
+24 28 0:23 / /sys rw,nosuid,nodev,noexec,relatime shared:6 - sysfs sys rw
+28 1 8:2 / / rw,noatime shared:1 - ext4 /dev/sda2 rw
+29 28 8:3 / /home rw,relatime shared:2 master:1 - ext4 /dev/sda3 rw,errors=remount-ro
+30 28 8:17 / /media/usb\040drive rw,nosuid,nodev,relatime - vfat /dev/sdb1 rw,fmask=0022
+31 28 8:3 /dummy/projects /srv/projects rw,relatime shared:2 - ext4 /dev/sda3 rw
+not a valid line
+32 28 0:40 / /srv/projects rw,relatime - tmpfs tmpfs rw,size=1024k
 "#;
 
     #[test]
     fn test_mount_point_probing() {
         let mut temp = NamedTempFile::new().unwrap();
+        write!(temp, "{}", TEST_MOUNTINFO).unwrap();
+
+        let mount_points = probe_mount_points_in(temp.path()).unwrap();
+
+        let prefixes: Vec<_> = mount_points
+            .iter()
+            .map(|mount_point| mount_point.fs_path_prefix.to_str().unwrap())
+            .collect();
+
+        assert_eq!(
+            prefixes,
+            [
+                "/media/usb drive",
+                // The tmpfs was mounted over the bind mount, so it must come first
+                "/srv/projects",
+                "/srv/projects",
+                "/home",
+                "/proc",
+                "/sys",
+                "/"
+            ]
+        );
+        assert_eq!(mount_points[1].fs_type, "tmpfs");
+
+        let mount_points: BTreeSet<_> = mount_points
+            .into_iter()
+            .map(|mount_point| (mount_point.mount_id, mount_point))
+            .collect();
+
+        let bind_mount = &mount_points.iter().find(|(id, _)| *id == 31).unwrap().1;
+        assert_eq!(
+            bind_mount,
+            &MountPoint {
+                fs_name: "/dev/sda3".into(),
+                fs_path_prefix: "/srv/projects".into(),
+                mount_id: 31,
+                parent_id: 28,
+                major: 8,
+                minor: 3,
+                root: "/dummy/projects".into(),
+                fs_type: "ext4".into(),
+                mount_options: "rw,relatime".into(),
+                super_options: "rw".into(),
+            }
+        );
+
+        let usb = &mount_points.iter().find(|(id, _)| *id == 30).unwrap().1;
+        assert_eq!(usb.fs_path_prefix, PathBuf::from("/media/usb drive"));
+        assert_eq!(usb.fs_name, "/dev/sdb1");
+        assert_eq!((usb.major, usb.minor), (8, 17));
+    }
 
-        let temp_path = temp.path();
-        let temp_path_cstr = CString::new(temp_path.as_os_str().as_bytes()).unwrap();
-
-        write!(temp, "{}", TEST_MTAB).unwrap();
-
-        let mount_points = probe_mount_points_in(&temp_path_cstr).unwrap();
-
-        let mount_points: BTreeSet<_> = mount_points.into_iter().collect();
-
-        let expected = vec![
-            MountPoint {
-                fs_name: "efivarfs".into(),
-                fs_path_prefix: "/sys/firmware/efi/efivars".into(),
-            },
-            MountPoint {
-                fs_name: "securityfs".into(),
-                fs_path_prefix: "/sys/kernel/security".into(),
-            },
-            MountPoint {
-                fs_name: "devpts".into(),
-                fs_path_prefix: "/dev/pts".into(),
-            },
-            MountPoint {
-                fs_name: "tmpfs".into(),
-                fs_path_prefix: "/dev/shm".into(),
-            },
-            MountPoint {
-                fs_name: "proc".into(),
-                fs_path_prefix: "/proc".into(),
-            },
-            MountPoint {
-                fs_name: "run".into(),
-                fs_path_prefix: "/run".into(),
-            },
-            MountPoint {
-                fs_name: "dev".into(),
-                fs_path_prefix: "/dev".into(),
-            },
-            MountPoint {
-                fs_name: "sys".into(),
-                fs_path_prefix: "/sys".into(),
-            },
-            MountPoint {
-                fs_name: "/dev/sda2".into(),
-                fs_path_prefix: "/".into(),
-            },
-        ];
-
-        let expected: BTreeSet<_> = expected.into_iter().collect();
-
-        assert_eq!(mount_points, expected);
+    #[test]
+    fn test_mountinfo_unescaping() {
+        let line = br"40 28 0:50 /a\134b /mnt/tab\011new\012line rw - tmpfs tmp\040fs rw";
+        let mount_point = MountPoint::from_mountinfo_line(line).unwrap();
+
+        assert_eq!(mount_point.root, PathBuf::from(r"/a\b"));
+        assert_eq!(
+            mount_point.fs_path_prefix,
+            PathBuf::from("/mnt/tab\tnew\nline")
+        );
+        assert_eq!(mount_point.fs_name, "tmp fs");
+
+        // Missing separator
+        assert!(MountPoint::from_mountinfo_line(b"40 28 0:50 / /mnt rw tmpfs tmpfs rw").is_none());
     }
 }
 
@@ -211,11 +291,13 @@ mod mount_point_ordering_tests {
         let first = MountPoint {
             fs_name: "portal".into(),
             fs_path_prefix: "/run/user/1000".into(),
+            ..Default::default()
         };
 
         let second = MountPoint {
             fs_name: "portal".into(),
             fs_path_prefix: "/run/user/1001/doc".into(),
+            ..Default::default()
         };
 
         assert!(first < second);
@@ -229,11 +311,13 @@ mod mount_point_ordering_tests {
         let first = MountPoint {
             fs_name: "portal".into(),
             fs_path_prefix: "/run/user/1000/doc".into(),
+            ..Default::default()
         };
 
         let second = MountPoint {
             fs_name: "portal".into(),
             fs_path_prefix: "/run/user/1001/doc".into(),
+            ..Default::default()
         };
 
         assert!(first != second);
@@ -242,11 +326,13 @@ mod mount_point_ordering_tests {
         let first = MountPoint {
             fs_name: "portal2".into(),
             fs_path_prefix: "/run/user/1000/doc".into(),
+            ..Default::default()
         };
 
         let second = MountPoint {
             fs_name: "portal".into(),
             fs_path_prefix: "/run/user/1000/doc".into(),
+            ..Default::default()
         };
 
         assert!(first != second);
@@ -255,11 +341,13 @@ mod mount_point_ordering_tests {
         let first = MountPoint {
             fs_name: "portal2".into(),
             fs_path_prefix: "/run/user/1000/doc".into(),
+            ..Default::default()
         };
 
         let second = MountPoint {
             fs_name: "portal".into(),
             fs_path_prefix: "/run/user/1001/doc".into(),
+            ..Default::default()
         };
 
         assert!(first != second);
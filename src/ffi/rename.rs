@@ -0,0 +1,64 @@
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use libc::AT_SYMLINK_NOFOLLOW;
+
+use crate::error::{Error, Result};
+
+/// Makes renameat2(2) fail with `EEXIST` instead of replacing the destination.
+/// Not exported by the `libc` crate, but stable across architectures.
+const RENAME_NOREPLACE: libc::c_uint = 1;
+
+/// Renames `from` to `to`, failing with `EEXIST` if `to` already exists,
+/// `from` and `to` being relative to the directories given by `from_dir` and `to_dir`.
+///
+/// Uses renameat2(2) with `RENAME_NOREPLACE`, which makes the check and the rename
+/// a single atomic operation. On kernels or filesystems which don't support it,
+/// this falls back to checking for `to` before calling renameat(2).
+pub(super) fn rename_noreplace_at(
+    from_dir: RawFd,
+    from: &CStr,
+    to_dir: RawFd,
+    to: &CStr,
+) -> Result<()> {
+    // Safety: both paths are valid NUL-terminated strings.
+    // renameat2 is called through syscall(2) since glibc only wraps it from 2.28 on
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            from_dir,
+            from.as_ptr(),
+            to_dir,
+            to.as_ptr(),
+            RENAME_NOREPLACE,
+        )
+    };
+
+    if ret != -1 {
+        return Ok(());
+    }
+
+    let io_err = io::Error::last_os_error();
+    match io_err.raw_os_error() {
+        Some(libc::ENOSYS) | Some(libc::EINVAL) => {
+            // Safety: The all-zero byte-pattern is a valid `struct stat`
+            let mut stat_buf = unsafe { mem::zeroed() };
+            // Safety: `to` is a valid NUL-terminated string and `stat_buf` a valid `struct stat`
+            let exists =
+                unsafe { libc::fstatat(to_dir, to.as_ptr(), &mut stat_buf, AT_SYMLINK_NOFOLLOW) }
+                    == 0;
+            if exists {
+                return Err(Error::Io(io::Error::from_raw_os_error(libc::EEXIST)));
+            }
+
+            // Safety: both paths are valid NUL-terminated strings
+            if -1 == unsafe { libc::renameat(from_dir, from.as_ptr(), to_dir, to.as_ptr()) } {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+            Ok(())
+        }
+        _ => Err(Error::Io(io_err)),
+    }
+}
@@ -1,7 +1,7 @@
 use std::{mem, time::Duration};
 
 use cstr::cstr;
-use libc::{c_char, localtime_r, size_t, time, tm};
+use libc::{c_char, c_int, localtime_r, mktime, size_t, time_t, tm};
 use unixstring::UnixString;
 
 use crate::error::Result;
@@ -20,19 +20,15 @@ extern "C" {
 }
 
 pub fn format_time(now: Duration) -> Result<String> {
-    let mut timestamp = now.as_secs();
+    let timestamp = now.as_secs() as time_t;
 
     // Safety: the all-zero byte-pattern is valid struct tm
     let mut new_time: tm = unsafe { mem::zeroed() };
 
-    // Safety: time is memory-safe
-    // TODO: it'd be better to call `time(NULL)` here
-    let ltime = unsafe { time(&mut timestamp as *mut u64 as *mut i64) };
-
     unsafe { tzset() };
 
     // Safety: localtime_r is memory safe, threadsafe.
-    unsafe { localtime_r(&ltime as *const i64, &mut new_time as *mut tm) };
+    unsafe { localtime_r(&timestamp as *const time_t, &mut new_time as *mut tm) };
 
     let mut char_buf: [c_char; BUF_SIZ] = [0; BUF_SIZ];
 
@@ -53,9 +49,41 @@ pub fn format_time(now: Duration) -> Result<String> {
     Ok(unx.to_string_lossy().into())
 }
 
+/// Parses a local time in the YYYY-MM-DDThh:mm:ss format (as written by `format_time`)
+/// into the duration since the Unix epoch.
+pub fn parse_time(timestamp: &str) -> Option<Duration> {
+    let (date, time_of_day) = timestamp.trim().split_once('T')?;
+
+    let mut date = date.splitn(3, '-').map(str::parse::<c_int>);
+    let mut time_of_day = time_of_day.splitn(3, ':').map(str::parse::<c_int>);
+
+    // Safety: the all-zero byte-pattern is valid struct tm
+    let mut broken_down: tm = unsafe { mem::zeroed() };
+
+    broken_down.tm_year = date.next()?.ok()? - 1900;
+    broken_down.tm_mon = date.next()?.ok()? - 1;
+    broken_down.tm_mday = date.next()?.ok()?;
+    broken_down.tm_hour = time_of_day.next()?.ok()?;
+    broken_down.tm_min = time_of_day.next()?.ok()?;
+    broken_down.tm_sec = time_of_day.next()?.ok()?;
+    // Let mktime figure out whether DST was in effect
+    broken_down.tm_isdst = -1;
+
+    unsafe { tzset() };
+
+    // Safety: mktime only reads and normalizes the given struct tm
+    let seconds = unsafe { mktime(&mut broken_down as *mut tm) };
+
+    if seconds < 0 {
+        return None;
+    }
+
+    Some(Duration::from_secs(seconds as u64))
+}
+
 #[cfg(test)]
 mod tests {
-    use std::time::{SystemTime, UNIX_EPOCH};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     use chrono::Local;
 
@@ -76,4 +104,27 @@ mod tests {
 
         assert_eq!(&rfc3339, &ffi::format_time(now).unwrap());
     }
+
+    #[test]
+    fn rfc3339_parsing() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("it seems that time went backwards!");
+
+        let rfc3339 = ffi::format_time(now).unwrap();
+
+        assert_eq!(ffi::parse_time(&rfc3339).unwrap().as_secs(), now.as_secs());
+
+        // The given time must be formatted, not the current one
+        let last_month = now - Duration::from_secs(30 * 24 * 60 * 60);
+        let rfc3339 = ffi::format_time(last_month).unwrap();
+        assert_eq!(
+            ffi::parse_time(&rfc3339).unwrap().as_secs(),
+            last_month.as_secs()
+        );
+
+        assert!(ffi::parse_time("2021-08-01").is_none());
+        assert!(ffi::parse_time("2021-08-01T10:00").is_none());
+        assert!(ffi::parse_time("yesterday").is_none());
+    }
 }
@@ -0,0 +1,80 @@
+use std::ffi::CStr;
+use std::ptr;
+
+use libc::{c_char, c_void, ssize_t};
+use unixstring::UnixString;
+
+use crate::error::{Error, Result};
+
+/// Calls `fill` with an empty buffer in order to learn the needed size,
+/// then again with a buffer of that size.
+fn read_into_buffer(fill: impl Fn(*mut c_void, usize) -> ssize_t) -> Result<Vec<u8>> {
+    loop {
+        let needed = fill(ptr::null_mut(), 0);
+        if needed == -1 {
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+
+        let mut buf = vec![0_u8; needed as usize];
+        let written = fill(buf.as_mut_ptr() as *mut c_void, buf.len());
+
+        if written == -1 {
+            let io_err = std::io::Error::last_os_error();
+            // The list (or value) grew in between both calls
+            if io_err.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(Error::Io(io_err));
+        }
+
+        buf.truncate(written as usize);
+        return Ok(buf);
+    }
+}
+
+/// The names of the extended attributes of the file given by `path`.
+/// Symbolic links are not followed.
+pub fn list_xattrs(path: &UnixString) -> Result<Vec<Vec<u8>>> {
+    // Safety: the buffer given by `read_into_buffer` is valid for `size` bytes
+    let names = read_into_buffer(|buf, size| unsafe {
+        libc::llistxattr(path.as_ptr(), buf as *mut c_char, size)
+    })?;
+
+    // The names are NUL-terminated and laid out one after the other
+    Ok(names
+        .split(|&byte| byte == 0)
+        .filter(|name| !name.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect())
+}
+
+/// The value of the extended attribute `name` of the file given by `path`.
+/// Symbolic links are not followed.
+pub fn get_xattr(path: &UnixString, name: &CStr) -> Result<Vec<u8>> {
+    // Safety: the buffer given by `read_into_buffer` is valid for `size` bytes
+    read_into_buffer(|buf, size| unsafe {
+        libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf, size)
+    })
+}
+
+/// Sets the extended attribute `name` of the file given by `path` to `value`.
+/// Symbolic links are not followed.
+pub fn set_xattr(path: &UnixString, name: &CStr, value: &[u8]) -> Result<()> {
+    // Safety: `value` is valid for `value.len()` bytes
+    let ret = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const c_void,
+            value.len(),
+            0,
+        )
+    };
+
+    if ret == -1 {
+        let io_err = std::io::Error::last_os_error();
+        Err(Error::Io(io_err))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    context::TrashContext,
+    error::{DangerousTarget, Error, Result},
+};
+
+/// Refuses `path`, as given by the user, when its last component is `.` or `..`.
+///
+/// Must be checked on the path as given: `Path` silently drops trailing `.` components,
+/// so `foo/.` would otherwise stand for `foo`.
+pub fn check_given_path(path: &Path) -> Result<()> {
+    let bytes = path.as_os_str().as_bytes();
+
+    let trimmed = match bytes.iter().rposition(|&byte| byte != b'/') {
+        Some(last) => &bytes[..=last],
+        // Only slashes: the filesystem root
+        None if !bytes.is_empty() => return Err(dangerous(path, DangerousTarget::Root)),
+        None => return Ok(()),
+    };
+
+    let file_name = match trimmed.iter().rposition(|&byte| byte == b'/') {
+        Some(idx) => &trimmed[idx + 1..],
+        None => trimmed,
+    };
+
+    if file_name == b"." || file_name == b".." {
+        return Err(dangerous(path, DangerousTarget::CurrentOrParentDir));
+    }
+
+    Ok(())
+}
+
+/// Refuses `path` when trashing it would take down the filesystem root, the user's home,
+/// a whole mount point or a trash directory of `ctx`.
+///
+/// Assumes that the parent of `path` is canonicalized.
+pub fn check_resolved_path(ctx: &TrashContext, path: &Path) -> Result<()> {
+    if path == Path::new("/") {
+        return Err(dangerous(path, DangerousTarget::Root));
+    }
+
+    if path == canonical(&ctx.home_dir) {
+        return Err(dangerous(path, DangerousTarget::HomeDir));
+    }
+
+    if ctx
+        .mount_points
+        .iter()
+        .any(|mount_point| path == canonical(&mount_point.fs_path_prefix))
+    {
+        return Err(dangerous(path, DangerousTarget::MountPoint));
+    }
+
+    // Trashing an ancestor of a trash directory would move that trash into itself
+    if trash_roots(ctx)
+        .iter()
+        .any(|root| path.starts_with(root) || root.starts_with(path))
+    {
+        return Err(dangerous(path, DangerousTarget::TrashDir));
+    }
+
+    Ok(())
+}
+
+/// The home trash along with the trash directories the user may have in every mount point,
+/// whether or not they exist
+fn trash_roots(ctx: &TrashContext) -> Vec<PathBuf> {
+    let topdir_trashes = ctx.mount_points.iter().flat_map(|mount_point| {
+        let topdir = &mount_point.fs_path_prefix;
+
+        vec![
+            topdir.join(".Trash"),
+            topdir.join(format!(".Trash-{}", ctx.uid)),
+        ]
+    });
+
+    std::iter::once(ctx.home_trash().root)
+        .chain(topdir_trashes)
+        .map(|root| canonical(&root))
+        .collect()
+}
+
+/// `path` with its symbolic links resolved if it exists, or as is otherwise
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+fn dangerous(path: &Path, reason: DangerousTarget) -> Error {
+    Error::DangerousTarget(path.to_owned(), reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::check_given_path;
+    use crate::error::{DangerousTarget, Error};
+
+    fn reason(path: &str) -> Option<DangerousTarget> {
+        match check_given_path(Path::new(path)) {
+            Ok(()) => None,
+            Err(Error::DangerousTarget(_, reason)) => Some(reason),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn current_and_parent_directories_are_refused() {
+        for path in &[
+            ".",
+            "..",
+            "./",
+            "../",
+            "foo/.",
+            "foo/..",
+            "/foo/..//",
+            "./.",
+        ] {
+            assert_eq!(
+                reason(path),
+                Some(DangerousTarget::CurrentOrParentDir),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn root_is_refused() {
+        assert_eq!(reason("/"), Some(DangerousTarget::Root));
+        assert_eq!(reason("//"), Some(DangerousTarget::Root));
+    }
+
+    #[test]
+    fn regular_paths_are_accepted() {
+        for path in &[
+            "foo", "./foo", "../foo", ".foo", "..foo", "foo/", "/a/b..", "",
+        ] {
+            assert_eq!(reason(path), None, "{}", path);
+        }
+    }
+}
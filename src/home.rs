@@ -1,7 +1,7 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::context::TrashContext;
 use crate::ffi;
-use crate::HOME_DIR;
 
 /// Attemps to find the calling user's home directory.
 /// Will check for the HOME env. variable first, falling back to
@@ -14,13 +14,21 @@ pub fn home_dir() -> Option<PathBuf> {
     }
 }
 
+/// The user's data directory: `$XDG_DATA_HOME` if set,
+/// falling back to `$HOME/.local/share` otherwise.
+pub fn xdg_data_dir(home_dir: &Path) -> PathBuf {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_xdg_data_dir(home_dir))
+}
+
+/// The default value of `$XDG_DATA_HOME`, as specified by the XDG Base Directory spec
+pub fn default_xdg_data_dir(home_dir: &Path) -> PathBuf {
+    home_dir.join(".local/share")
+}
+
 /// The path of the home trash directory, as specified by FreeDesktop's trash-spec 1.0
 /// Ref.: https://specifications.freedesktop.org/trash-spec/trashspec-1.0.html
-pub fn home_trash_path() -> PathBuf {
-    if let Some(xdg_home) = std::env::var_os("XDG_DATA_HOME") {
-        let xdg_home: PathBuf = xdg_home.into();
-        return xdg_home.join("Trash");
-    }
-
-    HOME_DIR.join(".local/share/Trash")
+pub fn home_trash_path(ctx: &TrashContext) -> PathBuf {
+    ctx.xdg_data_dir.join("Trash")
 }
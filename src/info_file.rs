@@ -1,18 +1,76 @@
-use std::ffi::OsStr;
-use std::fs::File;
+use std::ffi::{OsStr, OsString};
+use std::fs;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
 
-use crate::error::Result;
-use crate::ffi;
-use crate::trash::Trash;
+use crate::error::{Error, Result};
+use crate::ffi::{self, Dir};
+use crate::percent_encoding;
+use crate::trash::{directory_size, Trash};
 use std::time::Duration;
 
-pub fn make_info_file_path(file_name: &OsStr, trash_info_path: &Path) -> PathBuf {
+/// An item found in the trash, as described by its `.trashinfo` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+    /// The name of the item in `$trash/files`
+    pub trashed_name: OsString,
+    /// The path of the item in `$trash/files`
+    pub trashed_path: PathBuf,
+    /// The path of the item's `.trashinfo` file in `$trash/info`
+    pub info_path: PathBuf,
+    /// The location of the item before it was trashed
+    pub original_path: PathBuf,
+    /// The date and time of deletion, in the YYYY-MM-DDThh:mm:ss format
+    pub deletion_date: String,
+    /// The `$topdir` of the trash directory the item belongs to,
+    /// or `None` for the home trash
+    pub topdir: Option<PathBuf>,
+}
+
+impl TrashEntry {
+    /// The disk space used by the trashed item, in bytes.
+    ///
+    /// Files and directories alike are measured as `directory_size` does (as `du -B1` would),
+    /// so that sizes of both kinds can be added up.
+    pub fn size(&self) -> Result<u64> {
+        let metadata = fs::symlink_metadata(&self.trashed_path)?;
+
+        if metadata.is_dir() {
+            directory_size(&self.trashed_path)
+        } else {
+            // `st_blocks` is always given in 512-byte units
+            Ok(metadata.blocks() * 512)
+        }
+    }
+
+    /// The trash directory this item belongs to
+    pub fn trash(&self) -> Option<Trash> {
+        let root = self.info_path.parent()?.parent()?;
+
+        Some(match &self.topdir {
+            Some(topdir) => Trash::with_topdir(root, topdir),
+            None => Trash::new(root),
+        })
+    }
+
+    /// The parsed `DeletionDate`, as the duration since the Unix epoch
+    pub fn deletion_time(&self) -> Option<Duration> {
+        ffi::parse_time(&self.deletion_date)
+    }
+}
+
+/// The name of the info file of the item named `file_name` in `$trash/files`
+pub fn make_info_file_name(file_name: &OsStr) -> OsString {
     let mut file_name = file_name.to_owned();
     file_name.push(".trashinfo");
 
-    trash_info_path.join(file_name)
+    file_name
+}
+
+pub fn make_info_file_path(file_name: &OsStr, trash_info_path: &Path) -> PathBuf {
+    trash_info_path.join(make_info_file_name(file_name))
 }
 
 /// The $trash/info directory contains an “information file” for every file and directory in $trash/files. This file MUST have exactly the same name as the file or directory in $trash/files, plus the extension “.trashinfo”7.
@@ -29,22 +87,40 @@ pub fn build_info_file(
     file_name: &OsStr,
     trash: &Trash,
     deletion_date: Duration,
+) -> Result<()> {
+    let info_dir = Dir::open(&trash.info)?;
+
+    write_info_file(&info_dir, original_path, file_name, trash, deletion_date)
+}
+
+/// Same as `build_info_file`, the info file being created in `info_dir`,
+/// the already opened `$trash/info` directory.
+pub fn write_info_file(
+    info_dir: &Dir,
+    original_path: &Path,
+    file_name: &OsStr,
+    trash: &Trash,
+    deletion_date: Duration,
 ) -> Result<()> {
     // The date and time are to be in the YYYY-MM-DDThh:mm:ss format.
     // The time zone should be the user's (or filesystem's) local time.
     let rfc3339 = ffi::format_time(deletion_date)?;
 
-    // The info file is to be built in $trash/info
-    let info_path = &*trash.info;
-
     // This file MUST have exactly the same name as the file or directory in $trash/files, plus the extension “.trashinfo”.
-    let info_file_path = make_info_file_path(file_name, info_path);
+    let info_file_name = make_info_file_name(file_name);
 
-    let mut info_file = File::create(info_file_path)?;
+    // Created with O_CREAT | O_EXCL, so that the info file atomically reserves `file_name`:
+    // this fails with `EEXIST` if some other process already got it
+    let mut info_file = info_dir.open_file(
+        &info_file_name,
+        libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+        0o600,
+    )?;
 
     writeln!(info_file, "[Trash Info]")?;
-    // TODO: is this correct when `original_path` isn't valid UTF-8?
-    writeln!(info_file, "Path={}", original_path.display())?;
+    // The raw bytes of the path, escaped as in URLs
+    let escaped_path = percent_encoding::encode(info_file_path_value(original_path, trash));
+    writeln!(info_file, "Path={}", escaped_path)?;
     writeln!(info_file, "DeletionDate={}", &rfc3339)?;
 
     info_file.sync_all()?;
@@ -52,17 +128,92 @@ pub fn build_info_file(
     Ok(())
 }
 
+/// The value of the `Path` key for `original_path` when trashed into `trash`.
+///
+/// For trash directories in mount points, this is relative to their `$topdir`
+/// whenever `original_path` is under it. The home trash always gets absolute paths.
+pub fn info_file_path_value<'a>(original_path: &'a Path, trash: &Trash) -> &'a OsStr {
+    let relative_path = trash
+        .topdir
+        .as_deref()
+        .and_then(|topdir| original_path.strip_prefix(topdir).ok())
+        // A relative pathname MUST not include a “..” directory
+        .filter(|relative_path| {
+            relative_path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        })
+        .filter(|relative_path| !relative_path.as_os_str().is_empty());
+
+    relative_path.unwrap_or(original_path).as_os_str()
+}
+
+/// Reads the `.trashinfo` file given by `info_file_path`, which must be in `trash.info`.
+pub fn read_info_file(info_file_path: &Path, trash: &Trash) -> Result<TrashEntry> {
+    let invalid = || Error::InvalidInfoFile(info_file_path.to_owned());
+
+    let trashed_name = info_file_path
+        .file_name()
+        .and_then(|file_name| file_name.as_bytes().strip_suffix(b".trashinfo"))
+        .map(OsStr::from_bytes)
+        .ok_or_else(invalid)?;
+
+    let contents = fs::read(info_file_path)?;
+    let mut lines = contents
+        .split(|&byte| byte == b'\n')
+        .filter(|line| !line.is_empty());
+
+    // Its first line must be [Trash Info]
+    if lines.next() != Some(b"[Trash Info]") {
+        return Err(invalid());
+    }
+
+    let mut original_path = None;
+    let mut deletion_date = None;
+
+    for line in lines {
+        if let Some(path) = line.strip_prefix(b"Path=") {
+            let path = std::str::from_utf8(path).map_err(|_| invalid())?;
+            let path = PathBuf::from(percent_encoding::decode(path).ok_or_else(invalid)?);
+
+            // Relative paths are to be resolved from the directory the trash resides in
+            original_path = match trash.relative_paths_base() {
+                Some(base) if path.is_relative() => Some(base.join(path)),
+                _ => Some(path),
+            };
+        } else if let Some(date) = line.strip_prefix(b"DeletionDate=") {
+            deletion_date = Some(String::from_utf8_lossy(date).into_owned());
+        }
+    }
+
+    Ok(TrashEntry {
+        trashed_path: trash.files.join(trashed_name),
+        trashed_name: trashed_name.to_owned(),
+        info_path: info_file_path.to_owned(),
+        original_path: original_path.ok_or_else(invalid)?,
+        deletion_date: deletion_date.ok_or_else(invalid)?,
+        topdir: trash.topdir.clone(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
         ffi::{OsStr, OsString},
         fs::{self, File},
         io::Write,
+        os::unix::ffi::OsStrExt,
         path::{Path, PathBuf},
         time::{SystemTime, UNIX_EPOCH},
     };
 
-    use crate::{ffi, info_file, test::dummy_bytes, trash::Trash, HOME_DIR};
+    use crate::{
+        ffi,
+        info_file::{self, TrashEntry},
+        percent_encoding,
+        test::dummy_bytes,
+        trash::Trash,
+    };
 
     #[test]
     fn test_make_info_file_path() {
@@ -77,8 +228,7 @@ mod tests {
 
     #[test]
     fn test_build_info_file() {
-        let dir = tempfile::tempdir_in(&*HOME_DIR).unwrap();
-        // let dir = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
         let dir_path = dir.path();
         let trash = Trash::new(dir_path);
 
@@ -100,10 +250,153 @@ mod tests {
 
         let info_file_should_be = format!(
             "[Trash Info]\nPath={}\nDeletionDate={}\n",
-            dummy_file_path.display(),
+            percent_encoding::encode(dummy_file_path.as_os_str()),
             rfc3339
         );
 
         assert_eq!(info_file, info_file_should_be)
     }
+
+    #[test]
+    fn test_read_info_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::new(dir.path());
+        fs::create_dir(&trash.info).unwrap();
+
+        let original_path = Path::new("/home/dummy/some file");
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        info_file::build_info_file(original_path, OsStr::new("some file"), &trash, now).unwrap();
+
+        let info_file_path = trash.info.join("some file.trashinfo");
+        let entry = info_file::read_info_file(&info_file_path, &trash).unwrap();
+
+        assert_eq!(
+            entry,
+            TrashEntry {
+                trashed_name: "some file".into(),
+                trashed_path: trash.files.join("some file"),
+                info_path: info_file_path,
+                original_path: original_path.into(),
+                deletion_date: ffi::format_time(now).unwrap(),
+                topdir: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_read_invalid_info_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::new(dir.path());
+        fs::create_dir(&trash.info).unwrap();
+
+        let missing_header = trash.info.join("a.trashinfo");
+        fs::write(
+            &missing_header,
+            "Path=/a\nDeletionDate=2021-08-01T10:00:00\n",
+        )
+        .unwrap();
+        assert!(info_file::read_info_file(&missing_header, &trash).is_err());
+
+        let missing_date = trash.info.join("b.trashinfo");
+        fs::write(&missing_date, "[Trash Info]\nPath=/b\n").unwrap();
+        assert!(info_file::read_info_file(&missing_date, &trash).is_err());
+
+        let wrong_extension = trash.info.join("c.info");
+        fs::write(
+            &wrong_extension,
+            "[Trash Info]\nPath=/c\nDeletionDate=2021-08-01T10:00:00\n",
+        )
+        .unwrap();
+        assert!(info_file::read_info_file(&wrong_extension, &trash).is_err());
+
+        let bad_escape = trash.info.join("d.trashinfo");
+        fs::write(
+            &bad_escape,
+            "[Trash Info]\nPath=/d%zz\nDeletionDate=2021-08-01T10:00:00\n",
+        )
+        .unwrap();
+        assert!(info_file::read_info_file(&bad_escape, &trash).is_err());
+    }
+
+    #[test]
+    fn test_info_file_of_hostile_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::new(dir.path());
+        fs::create_dir(&trash.info).unwrap();
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+        let original_path = Path::new(OsStr::from_bytes(b"/tmp/new\nline 100% \xff"));
+        let file_name = original_path.file_name().unwrap();
+
+        info_file::build_info_file(original_path, file_name, &trash, now).unwrap();
+
+        let info_file_path = info_file::make_info_file_path(file_name, &trash.info);
+
+        // The info file must still be three lines long
+        let contents = fs::read_to_string(&info_file_path).unwrap();
+        assert_eq!(contents.lines().count(), 3);
+        assert!(contents.contains("Path=/tmp/new%0Aline%20100%25%20%FF\n"));
+
+        let entry = info_file::read_info_file(&info_file_path, &trash).unwrap();
+        assert_eq!(entry.original_path, original_path);
+        assert_eq!(entry.trashed_name, file_name);
+    }
+
+    #[test]
+    fn test_relative_paths_in_topdir_trash() {
+        let topdir = tempfile::tempdir().unwrap();
+        let topdir = topdir.path();
+        let trash = Trash::from_topdir(topdir, ffi::effective_user_id()).unwrap();
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let rfc3339 = ffi::format_time(now).unwrap();
+
+        let original_path = topdir.join("some dir/dummy");
+        info_file::build_info_file(&original_path, OsStr::new("dummy"), &trash, now).unwrap();
+
+        let info_file_path = trash.info.join("dummy.trashinfo");
+        assert_eq!(
+            fs::read_to_string(&info_file_path).unwrap(),
+            format!(
+                "[Trash Info]\nPath=some%20dir/dummy\nDeletionDate={}\n",
+                rfc3339
+            )
+        );
+
+        let entry = info_file::read_info_file(&info_file_path, &trash).unwrap();
+        assert_eq!(entry.original_path, original_path);
+
+        // Files not under the topdir must get absolute paths
+        let outside = Path::new("/elsewhere/dummy");
+        assert_eq!(info_file::info_file_path_value(outside, &trash), outside);
+
+        // The home trash always gets absolute paths
+        let home_trash = Trash::new(&topdir.join("Trash"));
+        assert_eq!(
+            info_file::info_file_path_value(&original_path, &home_trash),
+            original_path
+        );
+    }
+
+    #[test]
+    fn test_relative_paths_after_remounting() {
+        let old_mount = tempfile::tempdir().unwrap();
+        let trash = Trash::from_topdir(old_mount.path(), ffi::effective_user_id()).unwrap();
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let original_path = old_mount.path().join("dummy");
+        info_file::build_info_file(&original_path, OsStr::new("dummy"), &trash, now).unwrap();
+
+        // The same filesystem, now mounted somewhere else
+        let new_mount = tempfile::tempdir().unwrap();
+        let remounted_root = new_mount.path().join(trash.root.file_name().unwrap());
+        fs::rename(&trash.root, &remounted_root).unwrap();
+        let remounted = Trash::with_topdir(&remounted_root, new_mount.path());
+
+        let entry =
+            info_file::read_info_file(&remounted.info.join("dummy.trashinfo"), &remounted).unwrap();
+        assert_eq!(entry.original_path, new_mount.path().join("dummy"));
+    }
 }
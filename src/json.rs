@@ -0,0 +1,167 @@
+use std::{fmt, os::unix::ffi::OsStrExt, path::Path};
+
+/// A JSON object written on a single line, its fields kept in insertion order.
+///
+/// Only the few value types `tt` reports are supported: strings, unsigned numbers,
+/// arrays of bytes and `null`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct JsonObject {
+    /// Keys along with their already-encoded values
+    fields: Vec<(String, String)>,
+}
+
+impl JsonObject {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn string(mut self, key: &'static str, value: &str) -> Self {
+        self.fields.push((key.into(), encode_string(value)));
+        self
+    }
+
+    /// Adds `value` as a string, so that it can be given back to `tt` as is.
+    ///
+    /// JSON strings being Unicode, a path that isn't valid UTF-8 is added lossily,
+    /// along with its exact bytes as `<key>_bytes`.
+    pub fn path(mut self, key: &'static str, value: impl AsRef<Path>) -> Self {
+        let value = value.as_ref();
+
+        if let Some(value) = value.to_str() {
+            return self.string(key, value);
+        }
+
+        self = self.string(key, &value.to_string_lossy());
+        self.fields.push((
+            format!("{}_bytes", key),
+            encode_bytes(value.as_os_str().as_bytes()),
+        ));
+        self
+    }
+
+    pub fn number(mut self, key: &'static str, value: u64) -> Self {
+        self.fields.push((key.into(), value.to_string()));
+        self
+    }
+
+    pub fn null(mut self, key: &'static str) -> Self {
+        self.fields.push((key.into(), "null".into()));
+        self
+    }
+
+    /// Adds `value` with `add` if there is one, `null` otherwise
+    pub fn optional<T>(
+        self,
+        key: &'static str,
+        value: Option<T>,
+        add: impl FnOnce(Self, &'static str, T) -> Self,
+    ) -> Self {
+        match value {
+            Some(value) => add(self, key, value),
+            None => self.null(key),
+        }
+    }
+}
+
+impl fmt::Display for JsonObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("{")?;
+
+        for (idx, (key, value)) in self.fields.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}:{}", encode_string(key), value)?;
+        }
+
+        f.write_str("}")
+    }
+}
+
+/// Lists `bytes` as an array of numbers
+fn encode_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<_> = bytes.iter().map(u8::to_string).collect();
+
+    format!("[{}]", bytes.join(","))
+}
+
+/// Quotes `value`, escaping what RFC 8259 requires to be escaped
+fn encode_string(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len() + 2);
+    encoded.push('"');
+
+    for ch in value.chars() {
+        match ch {
+            '"' => encoded.push_str("\\\""),
+            '\\' => encoded.push_str("\\\\"),
+            '\n' => encoded.push_str("\\n"),
+            '\r' => encoded.push_str("\\r"),
+            '\t' => encoded.push_str("\\t"),
+            ch if ch < ' ' => encoded.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => encoded.push(ch),
+        }
+    }
+
+    encoded.push('"');
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+    use super::JsonObject;
+
+    #[test]
+    fn fields_keep_their_order() {
+        let object = JsonObject::new()
+            .string("outcome", "trashed")
+            .number("size", 1024)
+            .null("error")
+            .optional("missing", None::<u64>, JsonObject::number)
+            .optional("present", Some("yes"), JsonObject::string);
+
+        assert_eq!(
+            object.to_string(),
+            r#"{"outcome":"trashed","size":1024,"error":null,"missing":null,"present":"yes"}"#
+        );
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let object = JsonObject::new().string("name", "a \"quoted\"\\ name\n\t\u{1}é");
+
+        assert_eq!(
+            object.to_string(),
+            r#"{"name":"a \"quoted\"\\ name\n\t\u0001é"}"#
+        );
+    }
+
+    #[test]
+    fn paths_are_plain_strings() {
+        let path = Path::new("/tmp/a name with spaces, ç and \"quotes\"");
+
+        assert_eq!(
+            JsonObject::new().path("path", path).to_string(),
+            r#"{"path":"/tmp/a name with spaces, ç and \"quotes\""}"#
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_paths_come_with_their_bytes() {
+        let path = Path::new(OsStr::from_bytes(b"/a b\xFF"));
+
+        assert_eq!(
+            JsonObject::new()
+                .path("path", path)
+                .null("next")
+                .to_string(),
+            r#"{"path":"/a b�","path_bytes":[47,97,32,98,255],"next":null}"#
+        );
+    }
+
+    #[test]
+    fn empty_object() {
+        assert_eq!(JsonObject::new().to_string(), "{}");
+    }
+}
@@ -0,0 +1,160 @@
+//! `tt` sends files to the trash, as specified by the
+//! [FreeDesktop.org Trash spec](https://specifications.freedesktop.org/trash-spec/trashspec-1.0.html).
+//!
+//! Every operation takes a [`TrashContext`], which describes where the trash directories
+//! of a user are to be found. [`TrashContext::from_env`] gives the calling user's context.
+//!
+//! ```no_run
+//! let ctx = tt::TrashContext::from_env()?;
+//!
+//! tt::trash(&ctx, "some-file.txt")?;
+//!
+//! let listing = tt::list(&ctx);
+//! for entry in tt::find(&listing.entries, "some-file.txt") {
+//!     tt::restore(entry, false)?;
+//! }
+//! # Ok::<(), tt::Error>(())
+//! ```
+
+mod context;
+mod directory_sizes;
+mod empty;
+mod error;
+mod ffi;
+mod guard;
+mod home;
+mod info_file;
+mod list;
+mod move_file;
+mod percent_encoding;
+mod restore;
+mod trash;
+
+#[cfg(test)]
+mod test;
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+pub use context::{Clock, TrashContext};
+pub use directory_sizes::DirectorySize;
+pub use empty::{parse_age, parse_size, EmptyFilter, PurgeReport};
+pub use error::{DangerousTarget, Error, InvalidDirectory, InvalidSharedTrash, Result};
+pub use ffi::{probe_mount_points, MountPoint};
+pub use info_file::{build_info_file, read_info_file, TrashEntry};
+pub use list::TrashListing;
+pub use restore::{select_entry, Restored};
+pub use trash::{
+    directory_size, resolve_parent, send_to_trash, MoveMethod, Trash, TrashOptions, TrashPlan,
+    Trashed,
+};
+
+/// Sends the file given by `path` to the trash directory of the filesystem it resides in.
+///
+/// If `path` is a symbolic link, the link itself is trashed, not its target.
+/// Errors are returned as [`Error::CannotTrash`], along with `path` as given.
+///
+/// The filesystem root, the home directory, mount points, trash directories,
+/// and `.` or `..` are refused with [`Error::DangerousTarget`].
+///
+/// Returns the item as it now stands in the trash, along with the problems met once it
+/// was there, such as the `directorysizes` cache not being updated.
+pub fn trash(ctx: &TrashContext, path: impl AsRef<Path>) -> Result<Trashed> {
+    trash_with(ctx, path, TrashOptions::default())
+}
+
+/// Like [`trash`], with `options` deciding how the file is handled.
+pub fn trash_with(
+    ctx: &TrashContext,
+    path: impl AsRef<Path>,
+    options: TrashOptions,
+) -> Result<Trashed> {
+    let path = path.as_ref();
+
+    let trash_file = || {
+        let path = checked_path(ctx, path, options)?;
+        let trash = ctx.trash_for(&path)?;
+
+        trash::send_to_trash(ctx, path, &trash)
+    };
+
+    trash_file().map_err(|err| Error::CannotTrash(path.to_owned(), Box::new(err)))
+}
+
+/// Works out where [`trash_with`] would send the file given by `path`, with the same
+/// checks and errors, but without creating, moving or writing anything.
+pub fn plan(
+    ctx: &TrashContext,
+    path: impl AsRef<Path>,
+    options: TrashOptions,
+) -> Result<TrashPlan> {
+    let path = path.as_ref();
+
+    let plan_file = || {
+        let path = checked_path(ctx, path, options)?;
+        let trash = ctx.locate_trash_for(&path)?;
+
+        trash::plan_trash(&path, trash)
+    };
+
+    plan_file().map_err(|err| Error::CannotTrash(path.to_owned(), Box::new(err)))
+}
+
+/// Resolves the parent of `path`, refusing dangerous targets unless `options` say otherwise
+fn checked_path(ctx: &TrashContext, path: &Path, options: TrashOptions) -> Result<PathBuf> {
+    if !options.force_dangerous {
+        guard::check_given_path(path)?;
+    }
+
+    let resolved = trash::resolve_parent(path)?;
+
+    if !options.force_dangerous {
+        guard::check_resolved_path(ctx, &resolved)?;
+    }
+
+    Ok(resolved)
+}
+
+/// Every item in the home trash and in the trash directories of every mount point,
+/// sorted by deletion date.
+///
+/// Trash directories and info files which can't be read are skipped; their errors
+/// are returned along with the items.
+pub fn list(ctx: &TrashContext) -> TrashListing {
+    list::trashed_items(&ctx.all_trashes())
+}
+
+/// The items among `entries` whose original path or name in `$trash/files` is `query`.
+pub fn find(entries: &[TrashEntry], query: impl AsRef<OsStr>) -> Vec<&TrashEntry> {
+    restore::find_matches(entries, query.as_ref())
+}
+
+/// Moves a trashed item back to its original location, then removes its info file.
+///
+/// If the original location is occupied, the item is restored under a new unique
+/// name when `rename` is set, and [`Error::RestoreTargetExists`] is returned otherwise.
+///
+/// Returns the path the item was restored to, along with the problems met once it
+/// was there, such as the `directorysizes` cache not being updated.
+pub fn restore(entry: &TrashEntry, rename: bool) -> Result<Restored> {
+    restore::restore_entry(entry, rename)
+}
+
+/// Permanently deletes a single trashed item, along with its info file.
+///
+/// Returns the problems met once the item was gone, such as the `directorysizes` cache
+/// not being updated.
+pub fn purge_entry(entry: &TrashEntry) -> Result<Vec<Error>> {
+    empty::remove_entry(entry)
+}
+
+/// Permanently deletes every trashed item selected by `filter`,
+/// the age of items being measured with the clock of `ctx`.
+///
+/// Items which can't be removed don't stop the others from being removed; they are
+/// reported along with how many bytes were reclaimed.
+pub fn purge(ctx: &TrashContext, filter: EmptyFilter) -> PurgeReport {
+    empty::purge(&ctx.all_trashes(), filter, ctx.clock.now())
+}
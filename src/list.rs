@@ -0,0 +1,37 @@
+use crate::error::Error;
+use crate::info_file::TrashEntry;
+use crate::trash::Trash;
+
+/// Trashed items, along with what got in the way of finding the others
+#[derive(Debug, Default)]
+pub struct TrashListing {
+    /// The items which were found
+    pub entries: Vec<TrashEntry>,
+    /// Why some trash directories or info files couldn't be read
+    pub errors: Vec<Error>,
+}
+
+/// Collects the items of every given trash directory, sorted by deletion date.
+///
+/// Trash directories that can't be read are skipped, as `Error::CannotList`.
+pub fn trashed_items(trashes: &[Trash]) -> TrashListing {
+    let mut listing = TrashListing::default();
+
+    for trash in trashes {
+        match trash.entries() {
+            Ok(found) => {
+                listing.entries.extend(found.entries);
+                listing.errors.extend(found.errors);
+            }
+            Err(err) => listing
+                .errors
+                .push(Error::CannotList(trash.info.clone(), Box::new(err))),
+        }
+    }
+
+    listing
+        .entries
+        .sort_by(|a, b| a.deletion_date.cmp(&b.deletion_date));
+
+    listing
+}
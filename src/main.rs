@@ -1,62 +1,989 @@
-mod error;
-mod ffi;
-mod home;
-mod info_file;
-mod move_file;
-mod trash;
-
-#[cfg(test)]
-mod test;
-
 use std::{
     env,
+    ffi::OsString,
+    fmt, fs,
+    io::{self, BufRead, Write},
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use error::{Error, Result};
-use ffi::MountPoint;
-use lazy_static::lazy_static;
-use trash::Trash;
+mod json;
+
+use json::JsonObject;
+use tt::{
+    EmptyFilter, Error, MoveMethod, Result, TrashContext, TrashEntry, TrashOptions, TrashPlan,
+    Trashed,
+};
+
+/// Every file was handled
+const EXIT_OK: u8 = 0;
+/// Something went wrong, e.g. some of the given files couldn't be handled
+const EXIT_FAILURE: u8 = 1;
+/// The command line is invalid
+const EXIT_USAGE: u8 = 2;
+
+const USAGE: &str = "\
+Usage: tt [-dfiIrRv] [--dry-run] [--force-dangerous] [--json] [--] FILE...
+       tt --list [--json]
+       tt --restore [--rename] [--json] FILE...
+       tt --empty (--all | [--older-than AGE] [--keep-newest-bytes SIZE])";
 
-use crate::ffi::probe_mount_points;
+/// Reports an invalid command line
+fn usage_error(message: impl std::fmt::Display) -> ExitCode {
+    eprintln!("tt: {}\n{}", message, USAGE);
 
-lazy_static! {
-    pub static ref HOME_DIR: PathBuf =
-        home::home_dir().expect("failed to obtain user's home directory!");
-    pub static ref HOME_TRASH_ROOT: PathBuf = home::home_trash_path();
-    pub static ref HOME_TRASH: Trash = Trash::new(&HOME_TRASH_ROOT);
-    pub static ref MOUNT_POINTS: Vec<MountPoint> =
-        ffi::probe_mount_points().expect("Failed to probe mount points!");
+    ExitCode::from(EXIT_USAGE)
+}
+
+/// The exit code for the outcome of a command which either fully succeeds or fails
+fn exit_code(result: Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::from(EXIT_OK),
+        Err(err) => {
+            eprintln!("tt: {}", err);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+/// Reports problems which don't stop the command, such as unusable trash directories
+fn warn(errors: &[Error]) {
+    for err in errors {
+        eprintln!("tt: {}", err);
+    }
 }
 
-fn mount_point_of_file(path: &Path) -> Option<&MountPoint> {
-    for mount_point in MOUNT_POINTS.iter() {
-        if mount_point.contains(path) {
-            return Some(mount_point);
+/// When to ask the user before trashing files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Interactive {
+    Never,
+    /// Once for every file, as with `-i`
+    Always,
+    /// Once for the whole command, as with `-I`, when more than three files
+    /// are given or when trashing recursively
+    Once,
+}
+
+/// The files and options of `tt FILE...`, whose flags mirror those of rm(1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TrashArgs {
+    files: Vec<OsString>,
+    /// Ignore missing files, never prompt
+    force: bool,
+    interactive: Interactive,
+    /// Trash directories along with their contents
+    recursive: bool,
+    /// Trash empty directories
+    dirs: bool,
+    /// Report every trashed file
+    verbose: bool,
+    /// Report where files would go instead of trashing them
+    dry_run: bool,
+    /// Report every file as a JSON object
+    json: bool,
+    options: TrashOptions,
+}
+
+impl Default for TrashArgs {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            force: false,
+            interactive: Interactive::Never,
+            recursive: false,
+            dirs: false,
+            verbose: false,
+            dry_run: false,
+            json: false,
+            options: TrashOptions::default(),
+        }
+    }
+}
+
+/// Parses the flags and files given to `tt FILE...`.
+///
+/// Flags may be grouped (`-rf`) and given after files; `--` ends them.
+/// As with rm(1), the last of `-f`, `-i` and `-I` wins.
+fn parse_trash_args(args: impl Iterator<Item = OsString>) -> Result<TrashArgs> {
+    let mut parsed = TrashArgs::default();
+    let mut options_ended = false;
+
+    for arg in args {
+        let bytes = arg.as_bytes();
+        let invalid = || Error::InvalidArgument(arg.to_string_lossy().into());
+
+        if options_ended || bytes == b"-" || !bytes.starts_with(b"-") {
+            parsed.files.push(arg);
+            continue;
+        }
+
+        let flags: Vec<u8> = match arg.to_str() {
+            Some("--") => {
+                options_ended = true;
+                continue;
+            }
+            Some("--force") => vec![b'f'],
+            Some("--recursive") => vec![b'r'],
+            Some("--dir") => vec![b'd'],
+            Some("--verbose") => vec![b'v'],
+            Some("--dry-run") => {
+                parsed.dry_run = true;
+                continue;
+            }
+            Some("--json") => {
+                parsed.json = true;
+                continue;
+            }
+            Some("--force-dangerous") => {
+                parsed.options.force_dangerous = true;
+                continue;
+            }
+            _ if bytes.starts_with(b"--") => return Err(invalid()),
+            _ => bytes[1..].to_vec(),
+        };
+
+        for flag in flags {
+            match flag {
+                b'f' => {
+                    parsed.force = true;
+                    parsed.interactive = Interactive::Never;
+                }
+                b'i' => {
+                    parsed.force = false;
+                    parsed.interactive = Interactive::Always;
+                }
+                b'I' => {
+                    parsed.force = false;
+                    parsed.interactive = Interactive::Once;
+                }
+                b'r' | b'R' => parsed.recursive = true,
+                b'd' => parsed.dirs = true,
+                b'v' => parsed.verbose = true,
+                _ => return Err(invalid()),
+            }
         }
     }
 
-    None
+    Ok(parsed)
+}
+
+/// Asks the user a yes/no question, an answer starting with `y` or `Y` meaning yes
+fn confirm(question: &str, mut input: impl BufRead, mut output: impl Write) -> Result<bool> {
+    write!(output, "tt: {} ", question)?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+
+    Ok(answer.trim_start().starts_with(['y', 'Y']))
+}
+
+/// Asks the user whether the file described by `metadata` should be trashed
+fn confirm_file(path: &Path, metadata: &fs::Metadata) -> Result<bool> {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_symlink() {
+        "symbolic link"
+    } else if file_type.is_file() && metadata.len() == 0 {
+        "regular empty file"
+    } else if file_type.is_file() {
+        "regular file"
+    } else {
+        "file"
+    };
+
+    let question = format!("trash {} '{}'?", kind, path.display());
+
+    confirm(&question, io::stdin().lock(), io::stderr())
+}
+
+/// What became of a file given to `tt FILE...`
+enum Handled {
+    Trashed(Trashed),
+    Planned(TrashPlan),
+    /// Missing with `-f`, or declined by the user
+    Skipped,
+}
+
+/// The error a `CannotTrash` or `CannotRestore` error is about
+fn cause(err: &Error) -> &Error {
+    match err {
+        Error::CannotTrash(_, err) | Error::CannotRestore(_, err) => err,
+        err => err,
+    }
+}
+
+/// Describes an item of the trash as a JSON object, along with what happened to it.
+///
+/// Paths are given as is, so that `original_path` or `trashed_name` can be handed back
+/// to `tt --restore`; see `JsonObject::path` for paths that aren't valid UTF-8.
+fn entry_json(entry: &TrashEntry, size: Option<u64>, outcome: &str) -> JsonObject {
+    JsonObject::new()
+        .path("original_path", &entry.original_path)
+        .optional(
+            "trash_root",
+            entry.trash().map(|trash| trash.root),
+            JsonObject::path,
+        )
+        .path("trashed_name", &entry.trashed_name)
+        .path("info_file", &entry.info_path)
+        .string("deletion_date", &entry.deletion_date)
+        .optional("size", size, JsonObject::number)
+        .string("outcome", outcome)
+        .null("error")
+}
+
+/// Describes a file which never made it to (or out of) the trash as a JSON object
+fn path_json(path: &Path, outcome: &str, error: Option<&Error>) -> JsonObject {
+    JsonObject::new()
+        .path("original_path", path)
+        .null("trash_root")
+        .null("trashed_name")
+        .null("info_file")
+        .null("deletion_date")
+        .null("size")
+        .string("outcome", outcome)
+        .optional(
+            "error",
+            error.map(|err| cause(err).to_string()),
+            |json, key, err| json.string(key, &err),
+        )
+}
+
+/// Describes where a file would be trashed to as a JSON object
+fn plan_json(plan: &TrashPlan) -> JsonObject {
+    JsonObject::new()
+        .path("original_path", &plan.path)
+        .path("trash_root", &plan.trash.root)
+        .optional(
+            "trashed_name",
+            plan.trashed_path.file_name(),
+            JsonObject::path,
+        )
+        .path("info_file", &plan.info_path)
+        .null("deletion_date")
+        .null("size")
+        .string("outcome", "planned")
+        .null("error")
+}
+
+/// Prints where a file would be trashed to, and how
+fn print_plan(plan: &TrashPlan) {
+    let method = match plan.method {
+        MoveMethod::Rename => "rename",
+        MoveMethod::CopyFallback => "copy and delete",
+    };
+
+    println!("would trash '{}'", plan.path.display());
+    println!("  trash: {}", plan.trash.root.display());
+    if let Some(topdir) = &plan.trash.topdir {
+        println!("  topdir: {}", topdir.display());
+    }
+    println!("  files: {}", plan.trashed_path.display());
+    println!("  info: {}", plan.info_path.display());
+    println!("  method: {}", method);
+}
+
+/// Reports what became of `file`, as given to `tt FILE...`
+fn print_handled(file: &OsString, handled: &Handled, args: &TrashArgs) {
+    let path = Path::new(file);
+
+    match handled {
+        Handled::Trashed(Trashed { entry, .. }) if args.json => {
+            println!("{}", entry_json(entry, entry.size().ok(), "trashed"))
+        }
+        Handled::Trashed(_) if args.verbose => println!("trashed '{}'", path.display()),
+        Handled::Planned(plan) if args.json => println!("{}", plan_json(plan)),
+        Handled::Planned(plan) => print_plan(plan),
+        Handled::Skipped if args.json => println!("{}", path_json(path, "skipped", None)),
+        Handled::Trashed(_) | Handled::Skipped => {}
+    }
 }
 
-fn main() -> Result<()> {
+/// Trashes the file given by `file`, unless `args` or the user say otherwise
+fn trash_one(ctx: &TrashContext, file: &OsString, args: &TrashArgs) -> Result<Handled> {
+    let path = Path::new(file);
+    let cannot_trash = |err: io::Error| Error::CannotTrash(path.into(), Box::new(err.into()));
+
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if args.force && err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Handled::Skipped)
+        }
+        Err(err) => return Err(cannot_trash(err)),
+    };
+
+    if metadata.is_dir() && !args.recursive {
+        if !args.dirs {
+            return Err(cannot_trash(io::ErrorKind::IsADirectory.into()));
+        }
+
+        let is_empty = fs::read_dir(path).map_err(cannot_trash)?.next().is_none();
+        if !is_empty {
+            return Err(cannot_trash(io::ErrorKind::DirectoryNotEmpty.into()));
+        }
+    }
+
+    if args.dry_run {
+        return Ok(Handled::Planned(tt::plan(ctx, path, args.options)?));
+    }
 
-    for file in env::args_os().skip(1) {
-        let file = PathBuf::from(file).canonicalize()?;
+    if args.interactive == Interactive::Always && !confirm_file(path, &metadata)? {
+        return Ok(Handled::Skipped);
+    }
 
-        let mount_point = mount_point_of_file(file.as_ref())
-            .ok_or_else(|| Error::MountPointNotFound(file.clone()))?;
+    Ok(Handled::Trashed(tt::trash_with(ctx, path, args.options)?))
+}
 
-        let is_home = file.starts_with("/home") || mount_point.is_home();
+/// How many of the files given to `tt FILE...` were handled, and how
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Summary {
+    trashed: usize,
+    /// Reported with `--dry-run` rather than trashed
+    planned: usize,
+    skipped: usize,
+    failed: usize,
+}
 
-        if is_home {
-            trash::send_to_trash(file, &HOME_TRASH)?
+impl Summary {
+    fn exit_code(&self) -> u8 {
+        if self.failed == 0 {
+            EXIT_OK
         } else {
-            // TODO: buncha stuff
-            let trash = Trash::new(&mount_point.fs_path_prefix);
-            trash::send_to_trash(file, &trash)?
+            EXIT_FAILURE
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    /// e.g. `2 trashed, 1 failed`, leaving out what didn't happen
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = [
+            (self.trashed, "trashed"),
+            (self.planned, "planned"),
+            (self.skipped, "skipped"),
+            (self.failed, "failed"),
+        ];
+
+        let mut counts = counts.iter().filter(|(count, _)| *count > 0);
+        if let Some((count, outcome)) = counts.next() {
+            write!(f, "{} {}", count, outcome)?;
+        }
+        for (count, outcome) in counts {
+            write!(f, ", {} {}", count, outcome)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Trashes every given file, carrying on past the ones that fail
+fn trash_files(ctx: &TrashContext, args: &TrashArgs) -> Summary {
+    let mut summary = Summary::default();
+
+    for file in &args.files {
+        match trash_one(ctx, file, args) {
+            Ok(handled) => {
+                print_handled(file, &handled, args);
+
+                match &handled {
+                    Handled::Trashed(trashed) => {
+                        warn(&trashed.warnings);
+                        summary.trashed += 1;
+                    }
+                    Handled::Planned(_) => summary.planned += 1,
+                    Handled::Skipped => summary.skipped += 1,
+                }
+            }
+            Err(err) => {
+                if args.json {
+                    println!("{}", path_json(Path::new(file), "failed", Some(&err)));
+                } else {
+                    eprintln!("tt: {}", err);
+                }
+                summary.failed += 1;
+            }
+        }
+    }
+
+    summary
+}
+
+/// Trashes every given file once the user agreed to, as asked for by `-I`,
+/// then sums up what was done when something failed or with `-v`
+fn trash(ctx: &TrashContext, args: &TrashArgs) -> ExitCode {
+    let asks_once = !args.dry_run
+        && args.interactive == Interactive::Once
+        && (args.files.len() > 3 || args.recursive);
+
+    if asks_once {
+        let question = format!(
+            "trash {} argument{}{}?",
+            args.files.len(),
+            if args.files.len() == 1 { "" } else { "s" },
+            if args.recursive { " recursively" } else { "" }
+        );
+
+        match confirm(&question, io::stdin().lock(), io::stderr()) {
+            Ok(true) => {}
+            Ok(false) => return ExitCode::from(EXIT_OK),
+            Err(err) => return exit_code(Err(err)),
         }
     }
 
+    warn(&ctx.shared_trash_problems());
+
+    let summary = trash_files(ctx, args);
+    if summary.failed > 0 || args.verbose {
+        eprintln!("tt: {}", summary);
+    }
+
+    ExitCode::from(summary.exit_code())
+}
+
+/// Prints the deletion date, size and original path of every trashed item,
+/// or every trashed item as a JSON object when `json` is set
+fn list(ctx: &TrashContext, json: bool) -> Result<()> {
+    warn(&ctx.shared_trash_problems());
+
+    let listing = tt::list(ctx);
+    warn(&listing.errors);
+
+    for entry in listing.entries {
+        if json {
+            println!("{}", entry_json(&entry, entry.size().ok(), "listed"));
+            continue;
+        }
+
+        // The item in `$trash/files` may be missing even though its info file exists
+        let size = entry
+            .size()
+            .map(|size| size.to_string())
+            .unwrap_or_else(|_| "?".into());
+
+        println!(
+            "{}\t{}\t{}",
+            entry.deletion_date,
+            size,
+            entry.original_path.display()
+        );
+    }
+
     Ok(())
 }
+
+/// A trashed item which was restored
+struct Restored {
+    entry: TrashEntry,
+    /// The size of the item, measured while it was still in the trash
+    size: Option<u64>,
+    restored_to: PathBuf,
+    /// What went wrong once the item was out of the trash
+    warnings: Vec<Error>,
+}
+
+/// Restores the item among `entries` given by `query`.
+///
+/// When more than one trashed item matches the query, the user is asked to pick one.
+fn restore_one(entries: &[TrashEntry], query: &OsString, rename: bool) -> Result<Restored> {
+    let candidates = tt::find(entries, query);
+
+    let entry = match candidates.as_slice() {
+        [] => return Err(Error::NotInTrash(query.into())),
+        [entry] => entry,
+        _ => tt::select_entry(&candidates, io::stdin().lock(), io::stderr())?,
+    };
+
+    let size = entry.size().ok();
+    let restored = tt::restore(entry, rename)
+        .map_err(|err| Error::CannotRestore(query.into(), Box::new(err)))?;
+
+    Ok(Restored {
+        entry: entry.clone(),
+        size,
+        restored_to: restored.path,
+        warnings: restored.warnings,
+    })
+}
+
+/// Restores the items given by `queries`, carrying on past the ones that fail.
+///
+/// Every item is reported as a JSON object when `json` is set.
+fn restore(ctx: &TrashContext, queries: &[OsString], rename: bool, json: bool) -> ExitCode {
+    warn(&ctx.shared_trash_problems());
+
+    let mut listing = tt::list(ctx);
+    warn(&listing.errors);
+
+    let mut all_restored = true;
+
+    for query in queries {
+        let result = restore_one(&listing.entries, query, rename);
+
+        if let Ok(restored) = &result {
+            // So that a later query can't pick it again
+            listing.entries.retain(|entry| entry != &restored.entry);
+            warn(&restored.warnings);
+        }
+
+        match result {
+            Ok(restored) if json => println!(
+                "{}",
+                entry_json(&restored.entry, restored.size, "restored")
+                    .path("restored_path", &restored.restored_to)
+            ),
+            Ok(restored) => println!("Restored {}", restored.restored_to.display()),
+            Err(err) if json => {
+                println!("{}", path_json(Path::new(query), "failed", Some(&err)));
+                all_restored = false;
+            }
+            Err(err) => {
+                eprintln!("tt: {}", err);
+                all_restored = false;
+            }
+        }
+    }
+
+    ExitCode::from(if all_restored { EXIT_OK } else { EXIT_FAILURE })
+}
+
+/// Permanently deletes the trashed items selected by `filter`,
+/// then reports how much space was reclaimed and which items couldn't be removed
+fn empty(ctx: &TrashContext, filter: EmptyFilter) -> ExitCode {
+    warn(&ctx.shared_trash_problems());
+
+    let report = tt::purge(ctx, filter);
+
+    warn(&report.warnings);
+    warn(&report.errors);
+    println!("Reclaimed {} bytes", report.reclaimed_bytes);
+
+    ExitCode::from(if report.errors.is_empty() {
+        EXIT_OK
+    } else {
+        EXIT_FAILURE
+    })
+}
+
+/// Parses the `--older-than <age>` and `--keep-newest-bytes <size>` options of `tt --empty`.
+///
+/// Deleting every item can't be undone, so it must be asked for with `--all` rather than
+/// by giving no filter.
+fn parse_empty_filter(mut args: impl Iterator<Item = OsString>) -> Result<EmptyFilter> {
+    let mut filter = EmptyFilter::default();
+    let mut all = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .and_then(|value| value.into_string().ok())
+                .ok_or_else(|| Error::InvalidArgument(arg.to_string_lossy().into()))
+        };
+
+        match arg.to_str() {
+            Some("--all") => all = true,
+            Some("--older-than") => filter.older_than = Some(tt::parse_age(&value()?)?),
+            Some("--keep-newest-bytes") => {
+                filter.keep_newest_bytes = Some(tt::parse_size(&value()?)?)
+            }
+            _ => return Err(Error::InvalidArgument(arg.to_string_lossy().into())),
+        }
+    }
+
+    match (all, filter == EmptyFilter::default()) {
+        (true, true) | (false, false) => Ok(filter),
+        (false, true) => Err(Error::UnfilteredPurge),
+        (true, false) => Err(Error::InvalidArgument("--all".into())),
+    }
+}
+
+/// What `tt` was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Trash(TrashArgs),
+    List {
+        json: bool,
+    },
+    Restore {
+        queries: Vec<OsString>,
+        rename: bool,
+        json: bool,
+    },
+    Empty(EmptyFilter),
+}
+
+/// Parses the command line, without the program name.
+///
+/// Commands other than trashing are given as a leading option, never as a bare word:
+/// with `rm` aliased to `tt`, `rm list` must trash the file named `list`.
+fn parse_command(args: impl Iterator<Item = OsString>) -> Result<Command> {
+    let mut args = args.peekable();
+    let invalid = |arg: &OsString| Error::InvalidArgument(arg.to_string_lossy().into());
+
+    if args.peek().is_some_and(|arg| arg == "--list") {
+        let mut json = false;
+
+        for arg in args.skip(1) {
+            match arg.to_str() {
+                Some("--json") => json = true,
+                _ => return Err(invalid(&arg)),
+            }
+        }
+
+        return Ok(Command::List { json });
+    }
+
+    if args.peek().is_some_and(|arg| arg == "--restore") {
+        let (flags, queries): (Vec<_>, Vec<_>) = args
+            .skip(1)
+            .partition(|arg| arg == "--rename" || arg == "--json");
+
+        if queries.is_empty() {
+            return Err(Error::MissingOperand);
+        }
+
+        return Ok(Command::Restore {
+            queries,
+            rename: flags.iter().any(|flag| flag == "--rename"),
+            json: flags.iter().any(|flag| flag == "--json"),
+        });
+    }
+
+    if args.peek().is_some_and(|arg| arg == "--empty") {
+        return parse_empty_filter(args.skip(1)).map(Command::Empty);
+    }
+
+    let args = parse_trash_args(args)?;
+
+    // As with rm(1), `-f` makes a missing operand fine
+    if args.files.is_empty() && !args.force {
+        return Err(Error::MissingOperand);
+    }
+
+    Ok(Command::Trash(args))
+}
+
+/// Runs the command given by `args`, without the program name, in the context
+/// given by `context` once the command line is known to be valid
+fn run(
+    args: impl Iterator<Item = OsString>,
+    context: impl FnOnce() -> Result<TrashContext>,
+) -> ExitCode {
+    let command = match parse_command(args) {
+        Ok(command) => command,
+        Err(err) => return usage_error(err),
+    };
+
+    let ctx = match context() {
+        Ok(ctx) => ctx,
+        Err(err) => return exit_code(Err(err)),
+    };
+
+    match command {
+        Command::Trash(args) => trash(&ctx, &args),
+        Command::List { json } => exit_code(list(&ctx, json)),
+        Command::Restore {
+            queries,
+            rename,
+            json,
+        } => restore(&ctx, &queries, rename, json),
+        Command::Empty(filter) => empty(&ctx, filter),
+    }
+}
+
+fn main() -> ExitCode {
+    run(env::args_os().skip(1), TrashContext::from_env)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{OsStr, OsString},
+        fs, io,
+        os::unix::{ffi::OsStrExt, fs::MetadataExt},
+        path::Path,
+        process::ExitCode,
+    };
+
+    use tt::{EmptyFilter, Error, MountPoint, TrashContext, TrashEntry};
+
+    use super::{
+        confirm, entry_json, parse_command, parse_trash_args, path_json, run, trash_files, Command,
+        Interactive, Summary, TrashArgs, EXIT_FAILURE, EXIT_OK, EXIT_USAGE,
+    };
+
+    fn parse(args: &[&str]) -> tt::Result<TrashArgs> {
+        parse_trash_args(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn grouped_and_separate_flags() {
+        let args = parse(&["-rf", "a", "-v", "--dir", "b"]).unwrap();
+
+        assert_eq!(args.files, vec![OsString::from("a"), OsString::from("b")]);
+        assert!(args.recursive && args.force && args.verbose && args.dirs);
+        assert_eq!(args.interactive, Interactive::Never);
+        assert!(!args.options.force_dangerous);
+
+        assert!(parse(&["-R", "--force-dangerous"]).unwrap().recursive);
+        assert!(
+            parse(&["--force-dangerous"])
+                .unwrap()
+                .options
+                .force_dangerous
+        );
+    }
+
+    #[test]
+    fn last_of_force_and_interactive_wins() {
+        let args = parse(&["-f", "-i"]).unwrap();
+        assert!(!args.force);
+        assert_eq!(args.interactive, Interactive::Always);
+
+        let args = parse(&["-iIf"]).unwrap();
+        assert!(args.force);
+        assert_eq!(args.interactive, Interactive::Never);
+
+        assert_eq!(parse(&["-fI"]).unwrap().interactive, Interactive::Once);
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        let args = parse(&["-v", "--", "-f", "--", "-"]).unwrap();
+
+        assert_eq!(
+            args.files,
+            vec![OsString::from("-f"), "--".into(), "-".into()]
+        );
+        assert!(args.verbose);
+        assert!(!args.force);
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["-rx"]).is_err());
+        assert!(parse(&["--no-such-flag"]).is_err());
+    }
+
+    fn command(args: &[&str]) -> tt::Result<Command> {
+        parse_command(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn bare_words_are_files() {
+        for word in &["list", "restore", "empty"] {
+            match command(&[word]).unwrap() {
+                Command::Trash(args) => assert_eq!(args.files, vec![OsString::from(word)]),
+                other => panic!("`tt {}` was parsed as {:?}", word, other),
+            }
+        }
+
+        assert!(matches!(
+            command(&["-f", "restore", "--", "empty"]),
+            Ok(Command::Trash(args)) if args.files == ["restore", "empty"]
+        ));
+    }
+
+    #[test]
+    fn commands_are_options() {
+        assert_eq!(
+            command(&["--list", "--json"]).unwrap(),
+            Command::List { json: true }
+        );
+        assert_eq!(
+            command(&["--restore", "--rename", "a"]).unwrap(),
+            Command::Restore {
+                queries: vec!["a".into()],
+                rename: true,
+                json: false,
+            }
+        );
+        assert!(matches!(
+            command(&["--empty", "--older-than", "30d"]),
+            Ok(Command::Empty(filter)) if filter.older_than.is_some()
+        ));
+
+        assert_eq!(
+            command(&["--empty", "--all"]).unwrap(),
+            Command::Empty(EmptyFilter::default())
+        );
+
+        assert!(matches!(
+            command(&["--restore"]),
+            Err(Error::MissingOperand)
+        ));
+        assert!(matches!(command(&[]), Err(Error::MissingOperand)));
+        // Only as the first argument
+        assert!(command(&["a", "--list"]).is_err());
+    }
+
+    #[test]
+    fn emptying_everything_must_be_asked_for() {
+        assert!(matches!(command(&["--empty"]), Err(Error::UnfilteredPurge)));
+        assert!(matches!(
+            command(&["--empty", "--all", "--older-than", "1d"]),
+            Err(Error::InvalidArgument(arg)) if arg == "--all"
+        ));
+    }
+
+    #[test]
+    fn confirmation() {
+        let mut output = Vec::new();
+        assert!(confirm("trash 'a'?", "y\n".as_bytes(), &mut output).unwrap());
+        assert_eq!(output, b"tt: trash 'a'? ");
+
+        assert!(confirm("?", "Yes\n".as_bytes(), Vec::new()).unwrap());
+        assert!(!confirm("?", "n\n".as_bytes(), Vec::new()).unwrap());
+        assert!(!confirm("?", "\n".as_bytes(), Vec::new()).unwrap());
+        // End of input
+        assert!(!confirm("?", "".as_bytes(), Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn json_objects() {
+        let entry = TrashEntry {
+            trashed_name: "a-1".into(),
+            trashed_path: "/home/u/.local/share/Trash/files/a-1".into(),
+            info_path: "/home/u/.local/share/Trash/info/a-1.trashinfo".into(),
+            original_path: "/home/u/a".into(),
+            deletion_date: "2021-08-01T12:00:00".into(),
+            topdir: None,
+        };
+
+        assert_eq!(
+            entry_json(&entry, Some(3), "trashed").to_string(),
+            concat!(
+                r#"{"original_path":"/home/u/a","trash_root":"/home/u/.local/share/Trash","#,
+                r#""trashed_name":"a-1","info_file":"/home/u/.local/share/Trash/info/a-1.trashinfo","#,
+                r#""deletion_date":"2021-08-01T12:00:00","size":3,"outcome":"trashed","error":null}"#
+            )
+        );
+
+        let err = Error::CannotTrash(
+            "b".into(),
+            Box::new(io::Error::from(io::ErrorKind::IsADirectory).into()),
+        );
+        assert_eq!(
+            path_json(Path::new("b"), "failed", Some(&err)).to_string(),
+            concat!(
+                r#"{"original_path":"b","trash_root":null,"trashed_name":null,"info_file":null,"#,
+                r#""deletion_date":null,"size":null,"outcome":"failed","error":"is a directory"}"#
+            )
+        );
+    }
+
+    #[test]
+    fn json_paths_are_exact() {
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"bad\xFFname"));
+
+        assert_eq!(
+            path_json(path, "skipped", None).to_string(),
+            concat!(
+                r#"{"original_path":"bad�name","original_path_bytes":[98,97,100,255,110,97,109,101],"#,
+                r#""trash_root":null,"trashed_name":null,"info_file":null,"deletion_date":null,"#,
+                r#""size":null,"outcome":"skipped","error":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn json_paths_can_be_restored() {
+        let entry = TrashEntry {
+            trashed_name: "a b-ç".into(),
+            trashed_path: "/home/u/.local/share/Trash/files/a b-ç".into(),
+            info_path: "/home/u/.local/share/Trash/info/a b-ç.trashinfo".into(),
+            original_path: "/home/u/a b-ç".into(),
+            deletion_date: "2021-08-01T12:00:00".into(),
+            topdir: None,
+        };
+        let json = entry_json(&entry, None, "trashed").to_string();
+
+        // As a script would pick them out of the JSON object, then hand them to `tt --restore`
+        for query in &["/home/u/a b-ç", "a b-ç"] {
+            assert!(json.contains(&format!("\"{}\"", query)), "{}", json);
+            assert_eq!(tt::find(std::slice::from_ref(&entry), query), vec![&entry]);
+        }
+    }
+
+    /// A context whose home directory and only mount point is `root`
+    fn context(root: &Path) -> TrashContext {
+        let mount_point = MountPoint {
+            fs_name: "dummy".into(),
+            fs_path_prefix: root.to_owned(),
+            ..Default::default()
+        };
+
+        TrashContext::new(root, vec![mount_point], fs::metadata(root).unwrap().uid())
+    }
+
+    #[test]
+    fn trashing_carries_on_past_failures() {
+        let root = tempfile::tempdir().unwrap();
+        let ctx = context(root.path());
+        let (a, missing, b) = (
+            root.path().join("a"),
+            root.path().join("missing"),
+            root.path().join("b"),
+        );
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        let args = parse(&[
+            a.to_str().unwrap(),
+            missing.to_str().unwrap(),
+            b.to_str().unwrap(),
+        ])
+        .unwrap();
+        let summary = trash_files(&ctx, &args);
+
+        assert_eq!(
+            summary,
+            Summary {
+                trashed: 2,
+                failed: 1,
+                ..Summary::default()
+            }
+        );
+        assert_eq!(summary.to_string(), "2 trashed, 1 failed");
+        assert_eq!(summary.exit_code(), EXIT_FAILURE);
+        assert!(!a.exists() && !b.exists());
+        assert_eq!(tt::list(&ctx).entries.len(), 2);
+
+        // With `-f`, a missing file is no failure
+        let args = parse(&["-f", missing.to_str().unwrap()]).unwrap();
+        let summary = trash_files(&ctx, &args);
+        assert_eq!(summary.to_string(), "1 skipped");
+        assert_eq!(summary.exit_code(), EXIT_OK);
+    }
+
+    #[test]
+    fn exit_codes() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("file");
+        fs::write(&file, "").unwrap();
+
+        let run =
+            |args: &[&OsStr]| run(args.iter().map(OsString::from), || Ok(context(root.path())));
+        let (file, missing) = (file.as_os_str(), root.path().join("missing"));
+
+        assert_eq!(run(&[file]), ExitCode::from(EXIT_OK));
+        // The file is gone by now
+        assert_eq!(run(&[file]), ExitCode::from(EXIT_FAILURE));
+        assert_eq!(
+            run(&["-f".as_ref(), missing.as_os_str()]),
+            ExitCode::from(EXIT_OK)
+        );
+        assert_eq!(run(&["--list".as_ref()]), ExitCode::from(EXIT_OK));
+        assert_eq!(
+            run(&["--restore".as_ref(), missing.as_os_str()]),
+            ExitCode::from(EXIT_FAILURE)
+        );
+
+        assert_eq!(run(&[]), ExitCode::from(EXIT_USAGE));
+        assert_eq!(run(&["-x".as_ref()]), ExitCode::from(EXIT_USAGE));
+        assert_eq!(run(&["--empty".as_ref()]), ExitCode::from(EXIT_USAGE));
+    }
+}
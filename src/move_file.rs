@@ -1,43 +1,361 @@
-use std::fs;
-use std::path::Path;
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::error::Result;
+use unixstring::UnixString;
 
-/// Will copy the contents of `from` into `to`.
-/// `from` will then be deleted.
-fn clone_and_delete(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
-    fs::copy(from.as_ref(), to.as_ref())?;
-    if from.as_ref().is_dir() {
-        fs::remove_dir_all(from)?;
-    } else {
-        fs::remove_file(from)?;
+use crate::error::{Error, Result};
+use crate::ffi::{self, Dir, Lstat};
+
+/// The extended attributes carried over to copies.
+/// `system.posix_acl_*` hold the POSIX ACLs of a file.
+const PRESERVED_XATTR_PREFIXES: &[&[u8]] = &[
+    b"user.",
+    b"security.",
+    b"system.posix_acl_access",
+    b"system.posix_acl_default",
+];
+
+/// Ignores the errors given by `errnos`, which mean that an operation
+/// isn't permitted or supported rather than that it failed.
+fn ignoring(result: Result<()>, errnos: &[i32]) -> Result<()> {
+    match result {
+        Err(Error::Io(err))
+            if err
+                .raw_os_error()
+                .is_some_and(|errno| errnos.contains(&errno)) =>
+        {
+            Ok(())
+        }
+        other => other,
+    }
+}
+
+/// Copies the extended attributes (including POSIX ACLs) of `from` onto `to`.
+fn copy_xattrs(from: &UnixString, to: &UnixString) -> Result<()> {
+    let names = match ffi::list_xattrs(from) {
+        Ok(names) => names,
+        Err(Error::Io(err)) if err.raw_os_error() == Some(libc::ENOTSUP) => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    let preserved = names.into_iter().filter(|name| {
+        PRESERVED_XATTR_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    });
+
+    for name in preserved {
+        let name = CString::new(name)?;
+        let value = ffi::get_xattr(from, &name)?;
+
+        // The destination filesystem may not support them, `security.*` may need
+        // privileges and symlinks can't have `user.*` attributes
+        ignoring(
+            ffi::set_xattr(to, &name, &value),
+            &[libc::ENOTSUP, libc::EPERM],
+        )?;
     }
 
     Ok(())
 }
 
-pub fn move_file(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
-    if fs::rename(from.as_ref(), to.as_ref()).is_err() {
-        // rename(2) failed, likely because the files are in different mount points
-        // or are on separate filesystems.
-        clone_and_delete(from, to)?;
+/// Replicates the ownership, permissions, extended attributes and
+/// nanosecond-precision timestamps of `from` (within `from_dir`), whose status is `stat`,
+/// onto `to` (within `to_dir`).
+fn copy_attributes(
+    (from_dir, from): (&Dir, &OsStr),
+    (to_dir, to): (&Dir, &OsStr),
+    stat: &Lstat,
+) -> Result<()> {
+    // Only privileged users may give files away
+    ignoring(
+        to_dir.chown(to, stat.owner_user_id(), stat.owner_group_id()),
+        &[libc::EPERM],
+    )?;
+
+    // chown(2) may clear the setuid and setgid bits, so permissions come afterwards.
+    // Symbolic links have no permissions of their own
+    if !stat.is_symlink() {
+        to_dir.chmod(to, stat.mode() & 0o7777)?;
+    }
+
+    copy_xattrs(&from_dir.proc_path(from)?, &to_dir.proc_path(to)?)?;
+
+    // Timestamps come last, as everything else could change them
+    to_dir.set_times(
+        to,
+        (stat.accessed() as i64, stat.accessed_nsec()),
+        (stat.modified() as i64, stat.modified_nsec()),
+    )
+}
+
+/// Copies the file `from` within `from_dir` into `to` within `to_dir`,
+/// recursing into directories.
+///
+/// Symbolic links are copied as links, and FIFOs, sockets and device nodes
+/// are recreated (device nodes only when permitted). Ownership, permissions,
+/// extended attributes, POSIX ACLs and timestamps are preserved.
+/// Everything copied is fsynced before returning.
+fn copy_recursively(from_dir: &Dir, from: &OsStr, to_dir: &Dir, to: &OsStr) -> Result<()> {
+    let stat = from_dir.stat(from)?;
+    let (source, copy) = ((from_dir, from), (to_dir, to));
+
+    match stat.mode() & libc::S_IFMT {
+        libc::S_IFDIR => {
+            // Only the owner may write into the copy until everything was copied into it
+            to_dir.create_dir(to, 0o700)?;
+
+            let (from_subdir, to_subdir) = (from_dir.open_dir(from)?, to_dir.open_dir(to)?);
+            for name in from_subdir.entries()? {
+                copy_recursively(&from_subdir, &name, &to_subdir, &name)?;
+            }
+
+            copy_attributes(source, copy, &stat)?;
+            to_subdir.sync()?;
+        }
+        libc::S_IFLNK => {
+            to_dir.symlink(&from_dir.read_link(from)?, to)?;
+            copy_attributes(source, copy, &stat)?;
+        }
+        libc::S_IFREG => {
+            fail_point(Step::CopyFile)?;
+
+            let mut source_file = from_dir.open_file(from, libc::O_RDONLY, 0)?;
+            let mut copied_file =
+                to_dir.open_file(to, libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL, 0o600)?;
+
+            io::copy(&mut source_file, &mut copied_file)?;
+            copy_attributes(source, copy, &stat)?;
+            copied_file.sync_all()?;
+        }
+        // FIFOs, sockets, character and block devices
+        _ => {
+            to_dir.mknod(to, stat.mode(), stat.raw_device_id())?;
+            copy_attributes(source, copy, &stat)?;
+        }
     }
 
     Ok(())
 }
 
+/// The steps of `move_file` at which failures can be injected by tests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Step {
+    /// The initial rename(2), which fails with `EXDEV` to force the copy fallback
+    Rename,
+    /// Copying a regular file into the staging copy
+    CopyFile,
+    /// Renaming the staging copy into place
+    Commit,
+    /// Removing the source once it was copied
+    RemoveSource,
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The steps that should fail, each after how many times it was allowed to succeed
+    static FAILING_STEPS: std::cell::RefCell<Vec<(Step, usize)>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Makes `step` fail on this thread once it succeeded `successes` times.
+#[cfg(test)]
+pub(crate) fn fail_at(step: Step, successes: usize) {
+    FAILING_STEPS.with(|failing| failing.borrow_mut().push((step, successes)));
+}
+
+/// Returns an error if a test asked for `step` to fail.
+#[cfg(test)]
+fn fail_point(step: Step) -> io::Result<()> {
+    FAILING_STEPS.with(|failing| {
+        let mut failing = failing.borrow_mut();
+        let idx = match failing
+            .iter()
+            .position(|&(failing_step, _)| failing_step == step)
+        {
+            Some(idx) => idx,
+            None => return Ok(()),
+        };
+
+        if failing[idx].1 > 0 {
+            failing[idx].1 -= 1;
+            return Ok(());
+        }
+
+        failing.remove(idx);
+        let errno = match step {
+            Step::Rename => libc::EXDEV,
+            _ => libc::EIO,
+        };
+        Err(io::Error::from_raw_os_error(errno))
+    })
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+fn fail_point(_step: Step) -> io::Result<()> {
+    Ok(())
+}
+
+/// Removes the file `name` within `dir`, along with its contents if it's a directory.
+/// Symbolic links are removed as links.
+pub(crate) fn remove_recursively(dir: &Dir, name: &OsStr) -> Result<()> {
+    if !dir.stat(name)?.is_dir() {
+        return dir.unlink(name, false);
+    }
+
+    let subdir = dir.open_dir(name)?;
+    for entry in subdir.entries()? {
+        remove_recursively(&subdir, &entry)?;
+    }
+
+    dir.unlink(name, true)
+}
+
+/// A hidden name under which a file can be written before being renamed into place.
+///
+/// It doesn't include the final name, so that names close to `NAME_MAX` can be staged too.
+/// Names only differ from one call to the next, so they must be created with `create_staging`.
+fn staging_name() -> OsString {
+    // Tells apart the threads of this process
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.subsec_nanos());
+
+    format!(
+        ".tt-{}-{}-{}.tmp",
+        process::id(),
+        CALLS.fetch_add(1, Ordering::Relaxed),
+        nanos
+    )
+    .into()
+}
+
+/// Creates something under a fresh staging name with `create`, which must fail with `EEXIST`
+/// rather than reuse an existing file (as with `O_EXCL` or mkdir(2)).
+///
+/// Names already taken, such as by another process, are skipped. Returns the name
+/// along with what `create` returned, the caller being the only one to use that name.
+pub(crate) fn create_staging<T>(
+    mut create: impl FnMut(&OsStr) -> Result<T>,
+) -> Result<(OsString, T)> {
+    loop {
+        let name = staging_name();
+
+        match create(&name) {
+            Ok(created) => return Ok((name, created)),
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Fails with `EEXIST` if something is present at `name` within `dir`.
+fn ensure_vacant(dir: &Dir, name: &OsStr) -> Result<()> {
+    match dir.stat(name) {
+        Ok(_) => Err(io::Error::from_raw_os_error(libc::EEXIST).into()),
+        Err(Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Copies `from` into the staging directory `staging` within `to_dir`, then renames
+/// the copy out of it to `to`.
+fn copy_into_place(
+    (from_dir, from): (&Dir, &OsStr),
+    to_dir: &Dir,
+    staging: &OsStr,
+    to: &OsStr,
+) -> Result<()> {
+    let staging_dir = to_dir.open_dir(staging)?;
+
+    copy_recursively(from_dir, from, &staging_dir, to)?;
+
+    fail_point(Step::Commit)?;
+    // Never replace whatever appeared in `to` in the meantime
+    staging_dir.rename_noreplace(to, to_dir, to)?;
+
+    // Make sure the new directory entry itself reached the disk
+    to_dir.sync()
+}
+
+/// Will copy the contents of `from` into `to`, recursively if `from` is a directory.
+/// `from` will then be deleted, but only once everything was copied and synced.
+///
+/// The copy is made within a hidden staging directory next to `to`, and only renamed
+/// to `to` once complete, so `to` never holds a partial copy. If anything fails before
+/// `from` starts being deleted, the staging directory is removed and `from` is left untouched.
+fn clone_and_delete(from_dir: &Dir, from: &OsStr, to_dir: &Dir, to: &OsStr) -> Result<()> {
+    // Don't bother copying anything if it can't be renamed into place later on
+    ensure_vacant(to_dir, to)?;
+
+    // Created by this call only, so that nothing else is ever removed below
+    let (staging, ()) = create_staging(|name| to_dir.create_dir(name, 0o700))?;
+
+    let copied = copy_into_place((from_dir, from), to_dir, &staging, to);
+
+    // Left empty once the copy is in place, holding a partial copy otherwise
+    let _ = remove_recursively(to_dir, &staging);
+    copied?;
+
+    fail_point(Step::RemoveSource)?;
+    remove_recursively(from_dir, from)
+}
+
+/// Moves the file `from` within `from_dir` to `to` within `to_dir`,
+/// copying it over if they're on separate filesystems.
+///
+/// Never replaces an existing `to`: fails with `EEXIST` instead.
+pub fn move_file_at(from_dir: &Dir, from: &OsStr, to_dir: &Dir, to: &OsStr) -> Result<()> {
+    let renamed = fail_point(Step::Rename)
+        .map_err(Error::Io)
+        .and_then(|()| from_dir.rename_noreplace(from, to_dir, to));
+
+    match renamed {
+        // rename(2) failed because the files are on separate filesystems
+        Err(Error::Io(err)) if err.raw_os_error() == Some(libc::EXDEV) => {
+            clone_and_delete(from_dir, from, to_dir, to)
+        }
+        other => other,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::convert::TryInto;
-    use std::fs::File;
+    use std::convert::{TryFrom, TryInto};
+    use std::ffi::{CString, OsStr};
+    use std::fs::{self, File, Permissions};
     use std::io::Write;
+    use std::os::unix::fs::{symlink, FileTypeExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use std::sync::Barrier;
+    use std::thread;
 
     use unixstring::UnixString;
 
-    use crate::ffi::Lstat;
-    use crate::move_file;
+    use crate::error::Result;
+    use crate::ffi::{self, Dir, Lstat};
+    use crate::move_file::{self, Step};
     use crate::test::dummy_bytes;
 
+    fn clone_and_delete(from: &Path, to: &Path) -> Result<()> {
+        let (from_dir, from) = Dir::open_parent(from)?;
+        let (to_dir, to) = Dir::open_parent(to)?;
+
+        move_file::clone_and_delete(&from_dir, from, &to_dir, to)
+    }
+
+    fn move_file(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+        let (from_dir, from) = Dir::open_parent(from.as_ref())?;
+        let (to_dir, to) = Dir::open_parent(to.as_ref())?;
+
+        move_file::move_file_at(&from_dir, from, &to_dir, to)
+    }
+
     #[test]
     fn test_clone_and_delete() {
         let dir = tempfile::tempdir().unwrap();
@@ -57,7 +375,7 @@ mod tests {
         let new_path: UnixString = dir_path.join("moved_dummy").try_into().unwrap();
         // There shouldn't be anything here yet
         assert!(!new_path.as_path().exists());
-        move_file::clone_and_delete(file_path.as_path(), new_path.as_path()).unwrap();
+        clone_and_delete(file_path.as_path(), new_path.as_path()).unwrap();
 
         // This file shouldn't exist anymore!
         assert!(!file_path.as_path().exists());
@@ -95,7 +413,7 @@ mod tests {
         let new_path: UnixString = dir_path.join("moved_dummy").try_into().unwrap();
         // There shouldn't be anything here yet
         assert!(!new_path.as_path().exists());
-        move_file::move_file(&file_path, &new_path).unwrap();
+        move_file(&file_path, &new_path).unwrap();
 
         // This file shouldn't exist anymore!
         assert!(!file_path.as_path().exists());
@@ -113,4 +431,324 @@ mod tests {
 
         assert_eq!(prev_stat.accessed(), new_stat.accessed());
     }
+
+    #[test]
+    fn test_clone_and_delete_nested_tree() {
+        // Two temporary directories standing in for different mount points
+        let first_mount = tempfile::tempdir().unwrap();
+        let second_mount = tempfile::tempdir().unwrap();
+
+        let tree = first_mount.path().join("tree");
+        fs::create_dir_all(tree.join("a/b/c")).unwrap();
+        fs::create_dir(tree.join("empty")).unwrap();
+
+        let contents = dummy_bytes();
+        fs::write(tree.join("a/b/c/file"), &contents).unwrap();
+        fs::write(tree.join("top-level"), &contents).unwrap();
+        fs::set_permissions(tree.join("a/b"), Permissions::from_mode(0o750)).unwrap();
+
+        symlink("b/c/file", tree.join("a/relative-link")).unwrap();
+        symlink("/does/not/exist", tree.join("dangling-link")).unwrap();
+        symlink(tree.join("a"), tree.join("dir-link")).unwrap();
+
+        Dir::open(&tree)
+            .unwrap()
+            .mknod(OsStr::new("fifo"), libc::S_IFIFO | 0o644, 0)
+            .unwrap();
+
+        let new_tree = second_mount.path().join("tree");
+        clone_and_delete(&tree, &new_tree).unwrap();
+
+        // The whole source tree must be gone
+        assert!(!tree.exists());
+
+        assert_eq!(fs::read(new_tree.join("a/b/c/file")).unwrap(), contents);
+        assert_eq!(fs::read(new_tree.join("top-level")).unwrap(), contents);
+        assert!(new_tree.join("empty").is_dir());
+
+        let permissions = fs::metadata(new_tree.join("a/b")).unwrap().permissions();
+        assert_eq!(permissions.mode() & 0o777, 0o750);
+
+        // Links are copied as links, not followed
+        assert_eq!(
+            fs::read_link(new_tree.join("a/relative-link")).unwrap(),
+            Path::new("b/c/file")
+        );
+        assert_eq!(
+            fs::read(new_tree.join("a/relative-link")).unwrap(),
+            contents
+        );
+        assert_eq!(
+            fs::read_link(new_tree.join("dangling-link")).unwrap(),
+            Path::new("/does/not/exist")
+        );
+        assert!(fs::symlink_metadata(new_tree.join("dir-link"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+
+        assert!(fs::symlink_metadata(new_tree.join("fifo"))
+            .unwrap()
+            .file_type()
+            .is_fifo());
+    }
+
+    #[test]
+    fn test_clone_and_delete_keeps_source_on_failure() {
+        let first_mount = tempfile::tempdir().unwrap();
+        let second_mount = tempfile::tempdir().unwrap();
+
+        let tree = first_mount.path().join("tree");
+        fs::create_dir(&tree).unwrap();
+        fs::write(tree.join("file"), dummy_bytes()).unwrap();
+
+        // The destination is occupied, so copying must fail
+        let new_tree = second_mount.path().join("tree");
+        fs::create_dir(&new_tree).unwrap();
+
+        assert!(clone_and_delete(&tree, &new_tree).is_err());
+        assert!(tree.join("file").exists());
+    }
+
+    /// A POSIX ACL in the format of the `system.posix_acl_access` xattr,
+    /// granting read access to the user with ID 1234
+    fn dummy_acl() -> Vec<u8> {
+        const ACL_VERSION: u32 = 2;
+        let entries: [(u16, u16, u32); 5] = [
+            (0x01, 0o6, u32::MAX), // ACL_USER_OBJ
+            (0x02, 0o4, 1234),     // ACL_USER
+            (0x04, 0o4, u32::MAX), // ACL_GROUP_OBJ
+            (0x10, 0o4, u32::MAX), // ACL_MASK
+            (0x20, 0o0, u32::MAX), // ACL_OTHER
+        ];
+
+        let mut acl = ACL_VERSION.to_le_bytes().to_vec();
+        for (tag, perm, id) in entries.iter() {
+            acl.extend_from_slice(&tag.to_le_bytes());
+            acl.extend_from_slice(&perm.to_le_bytes());
+            acl.extend_from_slice(&id.to_le_bytes());
+        }
+        acl
+    }
+
+    #[test]
+    fn test_clone_and_delete_preserves_attributes() {
+        let first_mount = tempfile::tempdir().unwrap();
+        let second_mount = tempfile::tempdir().unwrap();
+
+        let tree = first_mount.path().join("tree");
+        fs::create_dir(&tree).unwrap();
+        fs::write(tree.join("file"), dummy_bytes()).unwrap();
+        fs::set_permissions(tree.join("file"), Permissions::from_mode(0o640)).unwrap();
+        symlink("file", tree.join("link")).unwrap();
+
+        let user_xattr = CString::new("user.tt.comment").unwrap();
+        let acl_xattr = CString::new("system.posix_acl_access").unwrap();
+
+        let file = UnixString::try_from(tree.join("file")).unwrap();
+        // The filesystem backing the temporary directory may not support these
+        let has_xattrs = ffi::set_xattr(&file, &user_xattr, b"dummy").is_ok();
+        let has_acls = ffi::set_xattr(&file, &acl_xattr, &dummy_acl()).is_ok();
+
+        let is_root = ffi::effective_user_id() == 0;
+        if is_root {
+            let tree_dir = Dir::open(&tree).unwrap();
+            tree_dir.chown(OsStr::new("file"), 1234, 1234).unwrap();
+        }
+
+        let paths = ["", "file", "link"];
+        for (idx, path) in paths.iter().enumerate() {
+            let path = tree.join(path);
+            let (dir, name) = Dir::open_parent(&path).unwrap();
+            let nsecs = 123_456_789 + idx as i64;
+            dir.set_times(name, (1_000_000_000, nsecs), (1_500_000_000, nsecs))
+                .unwrap();
+        }
+
+        let previous: Vec<_> = paths
+            .iter()
+            .map(|path| Lstat::lstat(&UnixString::try_from(tree.join(path)).unwrap()).unwrap())
+            .collect();
+
+        let new_tree = second_mount.path().join("tree");
+        clone_and_delete(&tree, &new_tree).unwrap();
+
+        for (path, prev_stat) in paths.iter().zip(previous) {
+            let new_stat =
+                Lstat::lstat(&UnixString::try_from(new_tree.join(path)).unwrap()).unwrap();
+
+            assert_eq!(prev_stat.mode(), new_stat.mode());
+            assert_eq!(prev_stat.owner_user_id(), new_stat.owner_user_id());
+            assert_eq!(prev_stat.owner_group_id(), new_stat.owner_group_id());
+            assert_eq!(
+                (prev_stat.accessed(), prev_stat.accessed_nsec()),
+                (new_stat.accessed(), new_stat.accessed_nsec())
+            );
+            assert_eq!(
+                (prev_stat.modified(), prev_stat.modified_nsec()),
+                (new_stat.modified(), new_stat.modified_nsec())
+            );
+        }
+
+        let new_file = UnixString::try_from(new_tree.join("file")).unwrap();
+        if is_root {
+            assert_eq!(Lstat::lstat(&new_file).unwrap().owner_user_id(), 1234);
+        }
+        if has_xattrs {
+            assert_eq!(ffi::get_xattr(&new_file, &user_xattr).unwrap(), b"dummy");
+        }
+        if has_acls {
+            assert_eq!(ffi::get_xattr(&new_file, &acl_xattr).unwrap(), dummy_acl());
+        }
+    }
+
+    /// A source tree holding two files, and a vacant path in another directory to move it to
+    fn dummy_tree() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+
+        let tree = dir.path().join("mount/tree");
+        fs::create_dir_all(tree.join("sub")).unwrap();
+        fs::write(tree.join("first"), dummy_bytes()).unwrap();
+        fs::write(tree.join("sub/second"), dummy_bytes()).unwrap();
+
+        let other_mount = dir.path().join("other-mount");
+        fs::create_dir(&other_mount).unwrap();
+
+        (dir, tree, other_mount.join("tree"))
+    }
+
+    fn assert_rolled_back(tree: &Path, new_tree: &Path) {
+        assert!(tree.join("first").exists());
+        assert!(tree.join("sub/second").exists());
+
+        assert!(fs::symlink_metadata(new_tree).is_err());
+        // No staging copy must be left behind
+        assert_eq!(fs::read_dir(new_tree.parent().unwrap()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_clone_and_delete_longest_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path();
+
+        // `NAME_MAX` bytes
+        let name = "x".repeat(255);
+        let from = dir_path.join("from");
+        fs::create_dir(&from).unwrap();
+        fs::write(from.join(&name), dummy_bytes()).unwrap();
+        fs::create_dir(dir_path.join("to")).unwrap();
+
+        clone_and_delete(&from.join(&name), &dir_path.join("to").join(&name)).unwrap();
+
+        assert!(!from.join(&name).exists());
+        assert!(dir_path.join("to").join(&name).exists());
+    }
+
+    #[test]
+    fn test_move_file_falls_back_to_copying() {
+        let (_dir, tree, new_tree) = dummy_tree();
+
+        move_file::fail_at(Step::Rename, 0);
+        move_file(&tree, &new_tree).unwrap();
+
+        assert!(!tree.exists());
+        assert!(new_tree.join("sub/second").exists());
+        assert_eq!(fs::read_dir(new_tree.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_clone_and_delete_rolls_back_failed_copy() {
+        // Fail on the first file, then on the second one after the first was copied
+        for successes in 0..2 {
+            let (_dir, tree, new_tree) = dummy_tree();
+
+            move_file::fail_at(Step::CopyFile, successes);
+            assert!(clone_and_delete(&tree, &new_tree).is_err());
+
+            assert_rolled_back(&tree, &new_tree);
+        }
+    }
+
+    #[test]
+    fn test_clone_and_delete_rolls_back_failed_commit() {
+        let (_dir, tree, new_tree) = dummy_tree();
+
+        move_file::fail_at(Step::Commit, 0);
+        assert!(clone_and_delete(&tree, &new_tree).is_err());
+
+        assert_rolled_back(&tree, &new_tree);
+    }
+
+    #[test]
+    fn test_clone_and_delete_keeps_copy_if_source_removal_fails() {
+        let (_dir, tree, new_tree) = dummy_tree();
+
+        move_file::fail_at(Step::RemoveSource, 0);
+        assert!(clone_and_delete(&tree, &new_tree).is_err());
+
+        // The copy is complete, so it's kept along with the source
+        assert!(tree.join("sub/second").exists());
+        assert!(new_tree.join("sub/second").exists());
+    }
+
+    #[test]
+    fn test_create_staging_skips_taken_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_handle = Dir::open(dir.path()).unwrap();
+
+        let mut attempts = Vec::new();
+        let (name, ()) = move_file::create_staging(|name| {
+            attempts.push(name.to_owned());
+            if attempts.len() == 1 {
+                // Taken by someone else meanwhile
+                fs::write(dir.path().join(name), b"theirs")?;
+            }
+            dir_handle.create_dir(name, 0o700)
+        })
+        .unwrap();
+
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(name, attempts[1]);
+        assert_eq!(fs::read(dir.path().join(&attempts[0])).unwrap(), b"theirs");
+    }
+
+    #[test]
+    fn test_concurrent_copies_into_a_directory() {
+        const THREADS: usize = 16;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        fs::create_dir(&from).unwrap();
+        fs::create_dir(&to).unwrap();
+
+        let barrier = Barrier::new(THREADS);
+        thread::scope(|scope| {
+            for idx in 0..THREADS {
+                let (from, to, barrier) = (&from, &to, &barrier);
+
+                scope.spawn(move || {
+                    let name = idx.to_string();
+                    fs::write(from.join(&name), &name).unwrap();
+
+                    barrier.wait();
+                    clone_and_delete(&from.join(&name), &to.join(&name)).unwrap();
+                });
+            }
+        });
+
+        // Every copy made it, and no staging directory was left behind
+        let mut names: Vec<_> = fs::read_dir(&to)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort_by_key(|name| name.parse::<usize>().unwrap());
+        assert_eq!(
+            names,
+            (0..THREADS).map(|idx| idx.to_string()).collect::<Vec<_>>()
+        );
+
+        for name in names {
+            assert_eq!(fs::read_to_string(to.join(&name)).unwrap(), name);
+        }
+    }
 }
@@ -0,0 +1,103 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+/// Whether or not `byte` can be written as-is, as defined by RFC 2396, section 2.
+///
+/// `/` is also kept as-is so that escaped paths remain readable.
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-_.!~*'()/".contains(&byte)
+}
+
+/// Escapes the bytes of `input` as done in URLs (RFC 2396, section 2).
+///
+/// Works on the raw bytes given by the filesystem, so non-UTF-8 names are preserved.
+pub fn encode(input: &OsStr) -> String {
+    let mut encoded = String::with_capacity(input.len());
+
+    for &byte in input.as_bytes() {
+        if is_unreserved(byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    encoded
+}
+
+/// Reverses `encode`, giving back the raw bytes of the escaped name.
+///
+/// Returns `None` if `input` contains a malformed escape sequence.
+pub fn decode(input: &str) -> Option<OsString> {
+    let mut bytes = input.bytes();
+    let mut decoded = Vec::with_capacity(input.len());
+
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let high = (bytes.next()? as char).to_digit(16)?;
+            let low = (bytes.next()? as char).to_digit(16)?;
+            decoded.push((high * 16 + low) as u8);
+        } else {
+            decoded.push(byte);
+        }
+    }
+
+    Some(OsString::from_vec(decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use super::{decode, encode};
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            encode(OsStr::new("/home/dummy/file.txt")),
+            "/home/dummy/file.txt"
+        );
+        assert_eq!(encode(OsStr::new("a b%c")), "a%20b%25c");
+        assert_eq!(encode(OsStr::new("ç")), "%C3%A7");
+        assert_eq!(encode(OsStr::from_bytes(b"\xff\n")), "%FF%0A");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode("/home/dummy/file.txt").unwrap(),
+            "/home/dummy/file.txt"
+        );
+        assert_eq!(decode("a%20b%25c").unwrap(), "a b%c");
+        assert_eq!(decode("%c3%a7").unwrap(), "ç");
+
+        for malformed in &["%", "%2", "%zz", "100%"] {
+            assert!(decode(malformed).is_none());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_of_hostile_names() {
+        let hostile: &[&[u8]] = &[
+            b"/tmp/new\nline",
+            b"/tmp/100%",
+            b"/tmp/%41",
+            b"/tmp/\xff\xfe invalid utf-8",
+            b"/tmp/Path=/etc/passwd",
+            b"/tmp/[Trash Info]\r\n",
+            b"/tmp/tab\there",
+            b"/tmp/emoji \xf0\x9f\x97\x91",
+        ];
+
+        for &name in hostile {
+            let encoded = encode(OsStr::from_bytes(name));
+
+            // Nothing that could break the key/value format may be left unescaped
+            assert!(encoded.bytes().all(|byte| byte.is_ascii_graphic()));
+            assert!(!encoded.contains('='));
+
+            assert_eq!(decode(&encoded).unwrap().as_bytes(), name);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    directory_sizes,
+    error::{Error, Result},
+    ffi::Dir,
+    info_file::TrashEntry,
+    move_file::move_file_at,
+    trash::candidate_file_names,
+};
+
+/// Finds the trashed items that match `query`, either by their original path
+/// or by their name in `$trash/files`.
+pub fn find_matches<'a>(entries: &'a [TrashEntry], query: &OsStr) -> Vec<&'a TrashEntry> {
+    let query_path = Path::new(query);
+
+    // The original file no longer exists, so we can't canonicalize it
+    let absolute_query = if query_path.is_relative() {
+        env::current_dir()
+            .map(|cwd| cwd.join(query_path))
+            .unwrap_or_else(|_| query_path.to_owned())
+    } else {
+        query_path.to_owned()
+    };
+
+    entries
+        .iter()
+        .filter(|entry| {
+            entry.original_path == absolute_query || entry.trashed_name.as_os_str() == query
+        })
+        .collect()
+}
+
+/// Asks the user which of the given `candidates` should be restored.
+pub fn select_entry<'a>(
+    candidates: &[&'a TrashEntry],
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<&'a TrashEntry> {
+    for (idx, entry) in candidates.iter().enumerate() {
+        writeln!(
+            output,
+            "{}) {}\t{}\t{}",
+            idx + 1,
+            entry.deletion_date,
+            entry.original_path.display(),
+            entry.trashed_path.display()
+        )?;
+    }
+    write!(
+        output,
+        "Which item should be restored? [1-{}] ",
+        candidates.len()
+    )?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+
+    answer
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|choice| choice.checked_sub(1))
+        .and_then(|idx| candidates.get(idx).copied())
+        .ok_or(Error::InvalidSelection(answer.trim().into()))
+}
+
+/// A trashed item which was moved back out of the trash
+#[derive(Debug)]
+pub struct Restored {
+    /// Where the item now is
+    pub path: PathBuf,
+    /// What went wrong once the item was out of the trash, as `Error::CannotUpdateCache`
+    pub warnings: Vec<Error>,
+}
+
+/// Moves the trashed item back to its original location, then removes its info file.
+///
+/// If the original location is occupied, the item is restored under a new unique
+/// name when `rename` is set, otherwise it's left untouched in the trash.
+///
+/// Failing to update the `directorysizes` cache is returned as a warning.
+pub fn restore_entry(entry: &TrashEntry, rename: bool) -> Result<Restored> {
+    let original_path = &entry.original_path;
+
+    let file_name = original_path
+        .file_name()
+        .ok_or_else(|| Error::FailedToObtainFileName(original_path.clone()))?;
+    let parent = original_path
+        .parent()
+        .ok_or_else(|| Error::FailedToObtainFileName(original_path.clone()))?;
+
+    fs::create_dir_all(parent)?;
+
+    let (trashed_dir, trashed_name) = Dir::open_parent(&entry.trashed_path)?;
+    let parent_dir = Dir::open(parent)?;
+
+    // The move never replaces anything, so a name is only known to be free once
+    // the item got there: `foo`, then `foo-1`, `foo-2` and so on when renaming
+    let mut candidates = candidate_file_names(file_name);
+    let restored_name = loop {
+        let candidate = candidates.next().expect("there are endless candidates");
+
+        match move_file_at(&trashed_dir, trashed_name, &parent_dir, &candidate) {
+            Ok(()) => break candidate,
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::AlreadyExists => {
+                if !rename {
+                    return Err(Error::RestoreTargetExists(original_path.clone()));
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    };
+    let destination = parent.join(restored_name);
+
+    // The info file is only removed once the item is back in place
+    fs::remove_file(&entry.info_path)?;
+
+    let warnings = directory_sizes::forget_entry(entry).into_iter().collect();
+
+    Ok(Restored {
+        path: destination,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, fs, io::Cursor};
+
+    use crate::{
+        error::Error,
+        ffi,
+        info_file::TrashEntry,
+        restore::{find_matches, restore_entry, select_entry},
+        test::{dummy_bytes, dummy_context},
+        trash::{self, Trash},
+    };
+
+    fn trash_dummy(name: &str) -> (tempfile::TempDir, Trash, TrashEntry) {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = Trash::from_topdir(dir.path(), ffi::effective_user_id()).unwrap();
+
+        let original_dir = dir.path().join("original");
+        fs::create_dir(&original_dir).unwrap();
+        let original_path = original_dir.join(name);
+        fs::write(&original_path, dummy_bytes()).unwrap();
+
+        trash::send_to_trash(&dummy_context(dir.path()), original_path, &trash).unwrap();
+
+        let entry = trash.entries().unwrap().entries.pop().unwrap();
+
+        (dir, trash, entry)
+    }
+
+    #[test]
+    fn test_find_matches() {
+        let (_dir, trash, entry) = trash_dummy("dummy");
+        let entries = trash.entries().unwrap().entries;
+
+        let by_path = find_matches(&entries, entry.original_path.as_os_str());
+        assert_eq!(by_path, vec![&entry]);
+
+        let by_name = find_matches(&entries, OsStr::new("dummy"));
+        assert_eq!(by_name, vec![&entry]);
+
+        assert!(find_matches(&entries, OsStr::new("/not/in/the/trash")).is_empty());
+    }
+
+    #[test]
+    fn test_restore_entry() {
+        let (_dir, _trash, entry) = trash_dummy("dummy");
+
+        // The parent directory should be recreated
+        fs::remove_dir(entry.original_path.parent().unwrap()).unwrap();
+
+        let restored_to = restore_entry(&entry, false).unwrap().path;
+
+        assert_eq!(restored_to, entry.original_path);
+        assert!(entry.original_path.exists());
+        assert!(!entry.trashed_path.exists());
+        assert!(!entry.info_path.exists());
+    }
+
+    #[test]
+    fn test_restore_entry_to_occupied_path() {
+        let (_dir, _trash, entry) = trash_dummy("dummy");
+        fs::write(&entry.original_path, "occupied").unwrap();
+
+        assert!(matches!(
+            restore_entry(&entry, false),
+            Err(Error::RestoreTargetExists(_))
+        ));
+        // Nothing must have been touched
+        assert!(entry.trashed_path.exists());
+        assert!(entry.info_path.exists());
+        assert_eq!(fs::read(&entry.original_path).unwrap(), b"occupied");
+
+        let restored_to = restore_entry(&entry, true).unwrap().path;
+        assert_eq!(restored_to, entry.original_path.with_file_name("dummy-1"));
+        assert!(restored_to.exists());
+        assert!(!entry.info_path.exists());
+        assert_eq!(fs::read(&entry.original_path).unwrap(), b"occupied");
+    }
+
+    #[test]
+    fn test_restore_entry_skips_dangling_symlinks() {
+        let (_dir, _trash, entry) = trash_dummy("dummy");
+        fs::write(&entry.original_path, "occupied").unwrap();
+
+        // Taken, even though it leads nowhere
+        let dangling = entry.original_path.with_file_name("dummy-1");
+        std::os::unix::fs::symlink("/nonexistent", &dangling).unwrap();
+
+        let restored_to = restore_entry(&entry, true).unwrap().path;
+
+        assert_eq!(restored_to, entry.original_path.with_file_name("dummy-2"));
+        assert!(restored_to.exists());
+        assert!(fs::symlink_metadata(&dangling)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+
+    #[test]
+    fn test_select_entry() {
+        let (_dir, _trash, first) = trash_dummy("dummy");
+        let (_dir2, _trash2, second) = trash_dummy("dummy");
+        let candidates = [&first, &second];
+
+        let mut output = Vec::new();
+        let selected = select_entry(&candidates, Cursor::new("2\n"), &mut output).unwrap();
+        assert_eq!(selected, &second);
+        assert!(!output.is_empty());
+
+        for invalid in &["0\n", "3\n", "foo\n", ""] {
+            assert!(select_entry(&candidates, Cursor::new(*invalid), Vec::new()).is_err());
+        }
+    }
+}
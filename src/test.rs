@@ -1,14 +1,81 @@
-use std::{ffi::OsString, fs, fs::File, io::Write, path::Path};
-
-use tempfile;
+use std::{
+    convert::TryFrom,
+    ffi::OsString,
+    fs::{self, File, Permissions},
+    io::Write,
+    os::unix::fs::{symlink, MetadataExt, PermissionsExt},
+    path::Path,
+    ptr,
+    sync::Barrier,
+    thread,
+    time::Duration,
+};
 
 use rand::{rngs::SmallRng, RngCore, SeedableRng};
+use unixstring::UnixString;
 
 use crate::{
-    trash::{self, make_unique_file_name, Trash},
-    HOME_DIR,
+    context::{Clock, TrashContext},
+    directory_sizes::read_directory_sizes,
+    empty::EmptyFilter,
+    error::{DangerousTarget, Error, InvalidDirectory, InvalidSharedTrash},
+    ffi::{self, MountPoint},
+    move_file::{self, Step},
+    trash::{self, topdir_trash_path, validate_shared_trash, MoveMethod, Trash, TrashOptions},
 };
 
+/// A fixed point in time: 2021-08-01T12:00:00Z
+pub const DUMMY_NOW: Duration = Duration::from_secs(1_627_819_200);
+
+/// A synthetic environment rooted at `root`, which is its only mount point.
+pub fn dummy_context(root: &Path) -> TrashContext {
+    let home_dir = root.join("home/dummy");
+
+    TrashContext {
+        xdg_data_dir: home_dir.join(".local/share"),
+        home_dir,
+        mount_points: vec![MountPoint {
+            fs_name: "dummy".into(),
+            fs_path_prefix: root.to_owned(),
+            ..Default::default()
+        }],
+        uid: ffi::effective_user_id(),
+        clock: Clock::Fixed(DUMMY_NOW),
+    }
+}
+
+/// A bind mount, undone when dropped
+pub struct BindMount {
+    target: UnixString,
+}
+
+impl BindMount {
+    /// Mounts `source` on `target`, or returns `None` if not permitted
+    /// (which takes `CAP_SYS_ADMIN`)
+    pub fn new(source: &Path, target: &Path) -> Option<Self> {
+        let source = UnixString::try_from(source.to_owned()).unwrap();
+        let target = UnixString::try_from(target.to_owned()).unwrap();
+
+        let ret = unsafe {
+            libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND,
+                ptr::null(),
+            )
+        };
+
+        (ret == 0).then_some(Self { target })
+    }
+}
+
+impl Drop for BindMount {
+    fn drop(&mut self) {
+        unsafe { libc::umount2(self.target.as_ptr(), libc::MNT_DETACH) };
+    }
+}
+
 pub fn dummy_bytes() -> Vec<u8> {
     let mut rng = SmallRng::from_entropy();
     let quantity = 1024 + rng.next_u32() % 1024;
@@ -19,11 +86,11 @@ pub fn dummy_bytes() -> Vec<u8> {
 
 #[test]
 fn test_send_to_trash() {
-    let dir = tempfile::tempdir_in(&*HOME_DIR).unwrap();
+    let dir = tempfile::tempdir().unwrap();
     let dir_path = dir.path();
+    let ctx = dummy_context(dir_path);
     let trash = Trash::new(dir_path);
 
-    fs::create_dir(&trash.directory_sizes).unwrap();
     fs::create_dir(&trash.files).unwrap();
     fs::create_dir(&trash.info).unwrap();
 
@@ -31,7 +98,7 @@ fn test_send_to_trash() {
     let mut dummy = File::create(&*dummy_path).unwrap();
     dummy.write_all(&dummy_bytes()).unwrap();
 
-    trash::send_to_trash(dummy_path.clone(), &trash).unwrap();
+    trash::send_to_trash(&ctx, dummy_path.clone(), &trash).unwrap();
 
     // This path should no longer exist!
     assert!(!dummy_path.exists());
@@ -44,29 +111,1056 @@ fn test_send_to_trash() {
     assert!(new_path.exists());
 }
 
-// TODO: this test could look better
 #[test]
-fn test_make_unique_file_name() {
+fn test_topdir_trash_without_shared_trash() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+
+    // $topdir/.Trash doesn't exist, so $topdir/.Trash-$uid should be used
+    let expected = topdir.join(".Trash-1000");
+    assert_eq!(topdir_trash_path(topdir, 1000), expected);
+
+    let uid = ffi::effective_user_id();
+    let trash = Trash::from_topdir(topdir, uid).unwrap();
+    assert_eq!(trash.root, topdir.join(format!(".Trash-{}", uid)));
+    assert!(trash.files.is_dir());
+    assert!(trash.info.is_dir());
+}
+
+#[test]
+fn test_topdir_trash_with_shared_trash() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+    let uid = ffi::effective_user_id();
+
+    let shared_trash = topdir.join(".Trash");
+    fs::create_dir(&shared_trash).unwrap();
+    fs::set_permissions(&shared_trash, Permissions::from_mode(0o1777)).unwrap();
+
+    // $topdir/.Trash exists and is valid, so $topdir/.Trash/$uid should be used
+    let expected = shared_trash.join(uid.to_string());
+    assert_eq!(topdir_trash_path(topdir, uid), expected);
+
+    let trash = Trash::from_topdir(topdir, uid).unwrap();
+    assert_eq!(trash.root, expected);
+    assert!(trash.files.is_dir());
+    assert!(trash.info.is_dir());
+
+    // $topdir/.Trash-$uid must not have been created
+    assert!(!topdir.join(format!(".Trash-{}", uid)).exists());
+}
+
+#[test]
+fn test_shared_trash_without_sticky_bit() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+    let uid = ffi::effective_user_id();
+
+    let shared_trash = topdir.join(".Trash");
+    fs::create_dir(&shared_trash).unwrap();
+    fs::set_permissions(&shared_trash, Permissions::from_mode(0o777)).unwrap();
+
+    assert!(matches!(
+        validate_shared_trash(&shared_trash, uid),
+        Err(Error::InvalidSharedTrash(_, InvalidSharedTrash::NotSticky))
+    ));
+
+    // Falls back to $topdir/.Trash-$uid
+    let trash = Trash::from_topdir(topdir, uid).unwrap();
+    assert_eq!(trash.root, topdir.join(format!(".Trash-{}", uid)));
+    assert!(!shared_trash.join(uid.to_string()).exists());
+}
+
+#[test]
+fn test_shared_trash_is_symlink() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+    let uid = ffi::effective_user_id();
+
+    let elsewhere = tempfile::tempdir().unwrap();
+    fs::set_permissions(elsewhere.path(), Permissions::from_mode(0o1777)).unwrap();
+
+    let shared_trash = topdir.join(".Trash");
+    symlink(elsewhere.path(), &shared_trash).unwrap();
+
+    assert!(matches!(
+        validate_shared_trash(&shared_trash, uid),
+        Err(Error::InvalidSharedTrash(_, InvalidSharedTrash::IsSymlink))
+    ));
+
+    let trash = Trash::from_topdir(topdir, uid).unwrap();
+    assert_eq!(trash.root, topdir.join(format!(".Trash-{}", uid)));
+    // Nothing must have been written through the link
+    assert!(!elsewhere.path().join(uid.to_string()).exists());
+}
+
+#[test]
+fn test_shared_trash_is_not_a_directory() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+    let uid = ffi::effective_user_id();
+
+    let shared_trash = topdir.join(".Trash");
+    fs::write(&shared_trash, b"").unwrap();
+
+    assert!(matches!(
+        validate_shared_trash(&shared_trash, uid),
+        Err(Error::InvalidSharedTrash(
+            _,
+            InvalidSharedTrash::NotADirectory
+        ))
+    ));
+
+    let trash = Trash::from_topdir(topdir, uid).unwrap();
+    assert_eq!(trash.root, topdir.join(format!(".Trash-{}", uid)));
+}
+
+#[test]
+fn test_shared_trash_with_wrong_owner() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+    let uid = ffi::effective_user_id();
+
+    let shared_trash = topdir.join(".Trash");
+    fs::create_dir(&shared_trash).unwrap();
+    fs::set_permissions(&shared_trash, Permissions::from_mode(0o1777)).unwrap();
+
+    // $topdir/.Trash/$uid exists but is owned by the current user, not by `uid + 1`
+    fs::create_dir(shared_trash.join((uid + 1).to_string())).unwrap();
+
+    assert!(matches!(
+        validate_shared_trash(&shared_trash, uid + 1),
+        Err(Error::InvalidSharedTrash(_, InvalidSharedTrash::WrongOwner))
+    ));
+
+    assert_eq!(
+        topdir_trash_path(topdir, uid + 1),
+        topdir.join(format!(".Trash-{}", uid + 1))
+    );
+}
+
+#[test]
+fn test_send_to_topdir_trash() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+
+    let ctx = dummy_context(topdir);
+    let trash = Trash::from_topdir(topdir, ctx.uid).unwrap();
+
+    let dummy_path = topdir.join("dummy");
+    let mut dummy = File::create(&dummy_path).unwrap();
+    dummy.write_all(&dummy_bytes()).unwrap();
+
+    trash::send_to_trash(&ctx, dummy_path.clone(), &trash).unwrap();
+
+    assert!(!dummy_path.exists());
+    assert!(trash.files.join("dummy").exists());
+    assert!(trash.info.join("dummy.trashinfo").exists());
+}
+
+#[test]
+fn test_directory_size() {
     let dir = tempfile::tempdir().unwrap();
     let dir_path = dir.path();
 
-    let filename = Path::new("foo");
+    fs::create_dir(dir_path.join("nested")).unwrap();
+    fs::write(dir_path.join("a"), dummy_bytes()).unwrap();
+    fs::write(dir_path.join("nested/b"), dummy_bytes()).unwrap();
+    // Only the link itself counts, never what it points to
+    symlink(dir_path.join("nested"), dir_path.join("nested/link")).unwrap();
+
+    // Same as `du -B1`: the sum of the blocks used by the directory and its contents
+    let expected: u64 = ["", "nested", "a", "nested/b", "nested/link"]
+        .iter()
+        .map(|path| fs::symlink_metadata(dir_path.join(path)).unwrap().blocks() * 512)
+        .sum();
+
+    assert_eq!(trash::directory_size(dir_path).unwrap(), expected);
+
+    assert!(trash::directory_size(dir_path.join("a")).is_err());
+}
+
+#[test]
+fn test_existing_topdir_trashes() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+    let uid = ffi::effective_user_id();
+
+    // Nothing should be created
+    assert!(trash::existing_topdir_trashes(topdir, uid).is_empty());
+    assert_eq!(fs::read_dir(topdir).unwrap().count(), 0);
+
+    let trash = Trash::from_topdir(topdir, uid).unwrap();
+    let found = trash::existing_topdir_trashes(topdir, uid);
+
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].root, trash.root);
+}
+
+#[test]
+fn test_synthetic_context() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    assert_eq!(
+        ctx.home_trash().root,
+        root.join("home/dummy/.local/share/Trash")
+    );
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    crate::trash(&ctx, &dummy_path).unwrap();
+    assert!(!dummy_path.exists());
+
+    let entries = crate::list(&ctx).entries;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].original_path, dummy_path);
+    // The deletion date is given by the context's clock
+    assert_eq!(entries[0].deletion_time(), Some(DUMMY_NOW));
+
+    // Nothing was trashed long enough ago
+    let filter = EmptyFilter {
+        older_than: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    assert_eq!(crate::purge(&ctx, filter).reclaimed_bytes, 0);
+    assert_eq!(crate::list(&ctx).entries.len(), 1);
+
+    let a_day_later = TrashContext {
+        clock: Clock::Fixed(DUMMY_NOW + Duration::from_secs(24 * 60 * 60)),
+        ..ctx
+    };
+    assert!(crate::purge(&a_day_later, filter).reclaimed_bytes > 0);
+    assert!(crate::list(&a_day_later).entries.is_empty());
+}
+
+#[test]
+fn test_mount_point_of_file_by_device() {
+    let root = tempfile::tempdir().unwrap();
+    let mut ctx = dummy_context(root.path());
+
+    let dummy_path = root.path().join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    assert_eq!(
+        ctx.mount_point_of_file(&dummy_path).unwrap().fs_path_prefix,
+        root.path()
+    );
+
+    let mount_point = |prefix: &str| MountPoint {
+        fs_path_prefix: prefix.into(),
+        ..Default::default()
+    };
+
+    // `/` contains `/proc/version` by path, but procfs is a different filesystem
+    ctx.mount_points = vec![mount_point("/")];
+    let err = ctx
+        .mount_point_of_file(Path::new("/proc/version"))
+        .unwrap_err();
+    assert!(matches!(err, Error::MountPointNotFound(_)));
+    assert_eq!(
+        Error::CannotTrash("/proc/version".into(), Box::new(err)).to_string(),
+        "cannot trash '/proc/version': its mount point was not found"
+    );
+
+    ctx.mount_points = vec![mount_point("/proc"), mount_point("/")];
+    assert_eq!(
+        ctx.mount_point_of_file(Path::new("/proc/version"))
+            .unwrap()
+            .fs_path_prefix,
+        Path::new("/proc")
+    );
+}
+
+#[test]
+fn test_home_trash_outside_of_home() {
+    for home in &["var/home/dummy", "srv/users/dummy", "net/nfs/dummy"] {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        let ctx = TrashContext {
+            home_dir: root.join(home),
+            xdg_data_dir: root.join(home).join(".local/share"),
+            ..dummy_context(root)
+        };
+        fs::create_dir_all(&ctx.home_dir).unwrap();
+
+        let dummy_path = ctx.home_dir.join("dummy");
+        fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+        // The home trash doesn't even exist yet, but it'd be on the same device
+        assert_eq!(
+            ctx.trash_for(&dummy_path).unwrap().root,
+            ctx.home_trash().root
+        );
+    }
+}
+
+#[test]
+fn test_topdir_trash_for_files_off_the_home_device() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let uid = ffi::effective_user_id();
+
+    // procfs is never on the same device as the temporary directory
+    let ctx = TrashContext {
+        home_dir: "/proc/dummy".into(),
+        xdg_data_dir: "/proc/dummy/.local/share".into(),
+        ..dummy_context(root)
+    };
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    assert_eq!(
+        ctx.trash_for(&dummy_path).unwrap().root,
+        root.join(format!(".Trash-{}", uid))
+    );
+}
+
+#[test]
+fn test_failed_copy_fallback_removes_info_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let ctx = dummy_context(dir.path());
+    let trash = Trash::from_topdir(dir.path(), ctx.uid).unwrap();
+
+    let dummy_path = dir.path().join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    // Behave as if the file were on another filesystem and the disk broke while copying it
+    move_file::fail_at(Step::Rename, 0);
+    move_file::fail_at(Step::CopyFile, 0);
+    assert!(trash::send_to_trash(&ctx, dummy_path.clone(), &trash).is_err());
+
+    assert!(dummy_path.exists());
+    assert_eq!(fs::read_dir(&trash.files).unwrap().count(), 0);
+    assert_eq!(fs::read_dir(&trash.info).unwrap().count(), 0);
+}
+
+#[test]
+fn test_copied_item_keeps_its_info_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let ctx = dummy_context(dir.path());
+    let trash = Trash::from_topdir(dir.path(), ctx.uid).unwrap();
+
+    let dummy_path = dir.path().join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    move_file::fail_at(Step::Rename, 0);
+    move_file::fail_at(Step::RemoveSource, 0);
+    assert!(trash::send_to_trash(&ctx, dummy_path.clone(), &trash).is_err());
+
+    // The item made it to the trash, so it must stay listed there
+    assert!(trash.files.join("dummy").exists());
+    assert!(trash.info.join("dummy.trashinfo").exists());
+}
+
+#[test]
+fn test_concurrent_trashing_of_same_named_files() {
+    const THREADS: usize = 16;
+    const FILES_PER_THREAD: usize = 8;
+
+    let dir = tempfile::tempdir().unwrap();
+    let ctx = dummy_context(dir.path());
+    let trash = Trash::from_topdir(dir.path(), ctx.uid).unwrap();
+
+    let mut expected_contents = Vec::new();
+    for thread in 0..THREADS {
+        for file in 0..FILES_PER_THREAD {
+            expected_contents.push(format!("{}-{}", thread, file));
+        }
+    }
+
+    let barrier = Barrier::new(THREADS);
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            let (ctx, trash, barrier) = (&ctx, &trash, &barrier);
+            let thread_dir = dir.path().join(format!("thread-{}", thread));
+            fs::create_dir(&thread_dir).unwrap();
+
+            scope.spawn(move || {
+                barrier.wait();
+                for file in 0..FILES_PER_THREAD {
+                    let dummy_path = thread_dir.join("dummy");
+                    fs::write(&dummy_path, format!("{}-{}", thread, file)).unwrap();
+                    trash::send_to_trash(ctx, dummy_path, trash).unwrap();
+                }
+            });
+        }
+    });
+
+    let entries = trash.entries().unwrap().entries;
+    assert_eq!(entries.len(), THREADS * FILES_PER_THREAD);
+    assert_eq!(
+        fs::read_dir(&trash.files).unwrap().count(),
+        THREADS * FILES_PER_THREAD
+    );
+
+    // Every info file must describe the item trashed under its name
+    let mut contents: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            let contents = fs::read_to_string(&entry.trashed_path).unwrap();
+            let thread = contents.split('-').next().unwrap();
+            assert_eq!(
+                entry.original_path,
+                dir.path().join(format!("thread-{}/dummy", thread))
+            );
+            contents
+        })
+        .collect();
+
+    contents.sort();
+    expected_contents.sort();
+    assert_eq!(contents, expected_contents);
+}
+
+#[test]
+fn test_send_to_trash_skips_names_taken_in_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let ctx = dummy_context(dir.path());
+    let trash = Trash::from_topdir(dir.path(), ctx.uid).unwrap();
+
+    // An item without an info file still occupies its name
+    fs::write(trash.files.join("dummy"), "orphan").unwrap();
+
+    let dummy_path = dir.path().join("dummy");
+    fs::write(&dummy_path, "dummy").unwrap();
+    trash::send_to_trash(&ctx, dummy_path, &trash).unwrap();
+
+    assert_eq!(fs::read(trash.files.join("dummy")).unwrap(), b"orphan");
+    assert_eq!(fs::read(trash.files.join("dummy-1")).unwrap(), b"dummy");
+    assert!(!trash.info.join("dummy.trashinfo").exists());
+    assert!(trash.info.join("dummy-1.trashinfo").exists());
+}
+
+#[test]
+fn test_trash_dangling_symlink() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    let link = root.join("dangling");
+    symlink("/does/not/exist", &link).unwrap();
+
+    crate::trash(&ctx, &link).unwrap();
+    assert!(fs::symlink_metadata(&link).is_err());
+
+    let entries = crate::list(&ctx).entries;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].original_path, link);
+    assert_eq!(
+        fs::read_link(&entries[0].trashed_path).unwrap(),
+        Path::new("/does/not/exist")
+    );
+}
+
+#[test]
+fn test_trash_symlink_to_directory() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    let target = root.join("target");
+    fs::create_dir(&target).unwrap();
+    fs::write(target.join("dummy"), dummy_bytes()).unwrap();
+
+    let link = root.join("link");
+    symlink(&target, &link).unwrap();
+
+    crate::trash(&ctx, &link).unwrap();
+
+    // The target and its contents are untouched
+    assert!(target.join("dummy").exists());
+    assert!(fs::symlink_metadata(&link).is_err());
+
+    let entries = crate::list(&ctx).entries;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].original_path, link);
+    assert!(fs::symlink_metadata(&entries[0].trashed_path)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+
+    // Links to directories aren't directories themselves
+    assert!(read_directory_sizes(&ctx.home_trash()).unwrap().is_empty());
+}
+
+#[test]
+fn test_trash_symlink_across_mounts() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let uid = ffi::effective_user_id();
+
+    let ctx = TrashContext {
+        home_dir: "/proc/dummy".into(),
+        xdg_data_dir: "/proc/dummy/.local/share".into(),
+        mount_points: vec![
+            MountPoint {
+                fs_path_prefix: root.join("mnt"),
+                ..Default::default()
+            },
+            MountPoint {
+                fs_path_prefix: root.to_owned(),
+                ..Default::default()
+            },
+        ],
+        ..dummy_context(root)
+    };
+
+    let target = root.join("mnt/target");
+    fs::create_dir(root.join("mnt")).unwrap();
+    fs::write(&target, dummy_bytes()).unwrap();
+
+    // The link resides in `root` but points into the mount point at `root/mnt`
+    let link = root.join("link");
+    symlink(&target, &link).unwrap();
+    crate::trash(&ctx, &link).unwrap();
+
+    assert!(target.exists());
+    assert!(root
+        .join(format!(".Trash-{}/files/link", uid))
+        .symlink_metadata()
+        .is_ok());
+    assert!(!root.join(format!("mnt/.Trash-{}", uid)).exists());
+
+    // A link pointing into a different filesystem altogether
+    let link = root.join("mnt/proc-link");
+    symlink("/proc/version", &link).unwrap();
+    crate::trash(&ctx, &link).unwrap();
+
+    assert!(Path::new("/proc/version").exists());
+    assert!(root
+        .join(format!("mnt/.Trash-{}/files/proc-link", uid))
+        .symlink_metadata()
+        .is_ok());
+}
+
+#[test]
+fn test_trash_errors_carry_the_path() {
+    let root = tempfile::tempdir().unwrap();
+    let ctx = dummy_context(root.path());
+
+    let missing = root.path().join("missing");
+    let err = crate::trash(&ctx, &missing).unwrap_err();
+
+    assert!(matches!(&err, Error::CannotTrash(path, _) if path == &missing));
+    assert_eq!(err.path(), Some(missing.as_path()));
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "cannot trash '{}': no such file or directory",
+            missing.display()
+        )
+    );
+}
+
+#[test]
+fn test_home_trash_gets_created_privately() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+    crate::trash(&ctx, &dummy_path).unwrap();
+
+    let home_trash = ctx.home_trash();
+    for dir in &[
+        &ctx.xdg_data_dir,
+        &home_trash.root,
+        &home_trash.files,
+        &home_trash.info,
+    ] {
+        let metadata = fs::symlink_metadata(dir).unwrap();
+        assert!(metadata.is_dir());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+        assert_eq!(metadata.uid(), ctx.uid);
+    }
+}
+
+#[test]
+fn test_existing_trash_directories_are_validated() {
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+    let uid = ffi::effective_user_id();
+    let trash = Trash::with_topdir(&topdir.join(format!(".Trash-{}", uid)), topdir);
+
+    // `files` leads somewhere else
+    let elsewhere = topdir.join("elsewhere");
+    fs::create_dir_all(&trash.root).unwrap();
+    fs::create_dir(&elsewhere).unwrap();
+    symlink(&elsewhere, &trash.files).unwrap();
+
+    assert!(matches!(
+        trash.ensure_exists(uid),
+        Err(Error::InvalidTrashDirectory(path, InvalidDirectory::IsSymlink)) if path == trash.files
+    ));
+
+    // Not a directory at all
+    fs::remove_file(&trash.files).unwrap();
+    fs::write(&trash.files, b"").unwrap();
+    let err = trash.ensure_exists(uid).unwrap_err();
+    assert!(matches!(
+        &err,
+        Error::InvalidTrashDirectory(path, InvalidDirectory::NotADirectory) if path == &trash.files
+    ));
+    assert_eq!(
+        Error::CannotTrash("a".into(), Box::new(err)).to_string(),
+        format!(
+            "cannot trash 'a': the trash directory '{}' can't be used: it is not a directory",
+            trash.files.display()
+        )
+    );
+
+    // Owned by someone else
+    fs::remove_file(&trash.files).unwrap();
+    trash.ensure_exists(uid).unwrap();
+    assert!(matches!(
+        trash.ensure_exists(uid + 1),
+        Err(Error::InvalidTrashDirectory(path, InvalidDirectory::WrongOwner)) if path == trash.root
+    ));
+}
+
+#[test]
+fn test_trash_directories_get_the_users_ownership() {
+    if ffi::effective_user_id() != 0 {
+        // Only root may create directories on someone else's behalf
+        return;
+    }
+
+    let topdir = tempfile::tempdir().unwrap();
+    let topdir = topdir.path();
+
+    let trash = Trash::from_topdir(topdir, 1234).unwrap();
+
+    assert_eq!(trash.root, topdir.join(".Trash-1234"));
+    for dir in &[&trash.root, &trash.files, &trash.info] {
+        assert_eq!(fs::symlink_metadata(dir).unwrap().uid(), 1234);
+    }
+}
+
+/// The reason `tt::trash` refused `path` for, if it did
+fn refusal(ctx: &TrashContext, path: &Path) -> Option<DangerousTarget> {
+    match crate::trash(ctx, path) {
+        Err(Error::CannotTrash(_, err)) => match *err {
+            Error::DangerousTarget(_, reason) => Some(reason),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[test]
+fn test_dangerous_targets_are_refused() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let mut ctx = dummy_context(root);
+
+    let mount_point = root.join("mnt");
+    fs::create_dir(&mount_point).unwrap();
+    ctx.mount_points.push(MountPoint {
+        fs_name: "mnt".into(),
+        fs_path_prefix: mount_point.clone(),
+        ..Default::default()
+    });
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+    crate::trash(&ctx, &dummy_path).unwrap();
+
+    let home_trash = ctx.home_trash();
+    let topdir_trash = Trash::from_topdir(root, ctx.uid).unwrap();
+
+    let cases = [
+        (Path::new("/").to_owned(), DangerousTarget::Root),
+        (ctx.home_dir.clone(), DangerousTarget::HomeDir),
+        (root.to_owned(), DangerousTarget::MountPoint),
+        (mount_point.clone(), DangerousTarget::MountPoint),
+        (home_trash.root.clone(), DangerousTarget::TrashDir),
+        (home_trash.files.join("dummy"), DangerousTarget::TrashDir),
+        (
+            home_trash.info.join("dummy.trashinfo"),
+            DangerousTarget::TrashDir,
+        ),
+        (topdir_trash.files.clone(), DangerousTarget::TrashDir),
+        (ctx.xdg_data_dir.clone(), DangerousTarget::TrashDir),
+        (mount_point.join("."), DangerousTarget::CurrentOrParentDir),
+        (mount_point.join(".."), DangerousTarget::CurrentOrParentDir),
+    ];
+
+    for (path, reason) in &cases {
+        assert_eq!(refusal(&ctx, path), Some(*reason), "{}", path.display());
+        assert!(path.symlink_metadata().is_ok());
+    }
+
+    // A symbolic link to the home directory is harmless, as only the link gets trashed
+    let home_link = root.join("home-link");
+    symlink(&ctx.home_dir, &home_link).unwrap();
+    crate::trash(&ctx, &home_link).unwrap();
+    assert!(ctx.home_dir.is_dir());
+}
+
+#[test]
+fn test_dangerous_targets_can_be_forced() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let mut ctx = dummy_context(root);
+
+    let mount_point = root.join("mnt");
+    fs::create_dir(&mount_point).unwrap();
+    ctx.mount_points.push(MountPoint {
+        fs_name: "mnt".into(),
+        fs_path_prefix: mount_point.clone(),
+        ..Default::default()
+    });
+
+    let forced = TrashOptions {
+        force_dangerous: true,
+    };
+
+    let err = crate::trash(&ctx, &mount_point).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "cannot trash '{}': it is a mount point (use --force-dangerous to override)",
+            mount_point.display()
+        )
+    );
+
+    crate::trash_with(&ctx, &mount_point, forced).unwrap();
+    assert!(!mount_point.exists());
+    assert!(ctx.home_trash().files.join("mnt").is_dir());
+}
+
+#[test]
+fn test_plan_touches_nothing() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    let plan = crate::plan(&ctx, &dummy_path, TrashOptions::default()).unwrap();
+    let home_trash = ctx.home_trash();
+
+    assert_eq!(plan.path, dummy_path);
+    assert_eq!(plan.trash.root, home_trash.root);
+    assert_eq!(plan.trashed_path, home_trash.files.join("dummy"));
+    assert_eq!(plan.info_path, home_trash.info.join("dummy.trashinfo"));
+    assert_eq!(plan.method, MoveMethod::Rename);
+
+    assert!(dummy_path.exists());
+    assert!(!ctx.xdg_data_dir.exists());
+
+    // Planning applies the same checks as trashing
+    assert!(matches!(
+        crate::plan(&ctx, root, TrashOptions::default()),
+        Err(Error::CannotTrash(_, err)) if matches!(*err, Error::DangerousTarget(..))
+    ));
+}
+
+#[test]
+fn test_plan_matches_trashing() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+    let home_trash = ctx.home_trash();
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+    crate::trash(&ctx, &dummy_path).unwrap();
+
+    // `dummy` is taken in `files`, `dummy-1` in `info` only
+    fs::write(home_trash.info.join("dummy-1.trashinfo"), b"").unwrap();
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    let plan = crate::plan(&ctx, &dummy_path, TrashOptions::default()).unwrap();
+    assert_eq!(plan.trashed_path, home_trash.files.join("dummy-2"));
+    assert_eq!(plan.info_path, home_trash.info.join("dummy-2.trashinfo"));
+    assert!(!plan.trashed_path.exists());
+
+    crate::trash(&ctx, &dummy_path).unwrap();
+    assert!(plan.trashed_path.exists());
+    assert!(plan.info_path.exists());
+}
+
+#[test]
+fn test_plan_across_devices_copies() {
+    let root = tempfile::tempdir().unwrap();
+    let dummy_path = root.path().join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    // procfs is never on the same device as the temporary directory
+    let plan = trash::plan_trash(&dummy_path, Trash::new(Path::new("/proc/dummy/Trash"))).unwrap();
+
+    assert_eq!(plan.method, MoveMethod::CopyFallback);
+    assert_eq!(
+        plan.trashed_path,
+        Path::new("/proc/dummy/Trash/files/dummy")
+    );
+}
+
+#[test]
+fn test_trash_returns_the_entry() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+    crate::trash(&ctx, &dummy_path).unwrap();
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    let entry = crate::trash(&ctx, &dummy_path).unwrap().entry;
+
+    assert_eq!(entry.trashed_name, OsString::from("dummy-1"));
+    assert_eq!(entry.trash().unwrap().root, ctx.home_trash().root);
+    assert_eq!(
+        Some(&entry),
+        crate::list(&ctx)
+            .entries
+            .iter()
+            .find(|listed| listed.trashed_name == entry.trashed_name)
+    );
+}
+
+#[test]
+fn test_stacked_mounts_list_each_trash_once() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let mut ctx = dummy_context(root);
+
+    // mountinfo lists stacked mounts once per layer
+    let stacked = ctx.mount_points[0].clone();
+    ctx.mount_points.push(stacked);
+
+    let trash = Trash::from_topdir(root, ctx.uid).unwrap();
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+    trash::send_to_trash(&ctx, dummy_path.clone(), &trash).unwrap();
+
+    assert_eq!(ctx.all_trashes().len(), 1);
+    assert_eq!(
+        crate::find(&crate::list(&ctx).entries, &dummy_path).len(),
+        1
+    );
+}
+
+#[test]
+fn test_shared_trash_problems_are_returned_once() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let mut ctx = dummy_context(root);
+    assert!(ctx.shared_trash_problems().is_empty());
+
+    let stacked = ctx.mount_points[0].clone();
+    ctx.mount_points.push(stacked);
+    fs::write(root.join(".Trash"), b"").unwrap();
+
+    let problems = ctx.shared_trash_problems();
+    assert_eq!(problems.len(), 1);
+    assert!(matches!(
+        &problems[0],
+        Error::InvalidSharedTrash(path, InvalidSharedTrash::NotADirectory) if path == &root.join(".Trash")
+    ));
+}
+
+#[test]
+fn test_list_returns_unreadable_info_files() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+    crate::trash(&ctx, &dummy_path).unwrap();
+
+    let invalid_info = ctx.home_trash().info.join("invalid.trashinfo");
+    fs::write(&invalid_info, b"not an info file").unwrap();
+
+    let listing = crate::list(&ctx);
+    assert_eq!(listing.entries.len(), 1);
+    assert_eq!(listing.entries[0].original_path, dummy_path);
+    assert!(matches!(
+        listing.errors.as_slice(),
+        [Error::InvalidInfoFile(path)] if path == &invalid_info
+    ));
+}
+
+#[test]
+fn test_cache_failures_are_warnings() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    // Makes every update of the cache fail
+    let trash = ctx.home_trash();
+    trash.ensure_exists(ctx.uid).unwrap();
+    fs::create_dir(&trash.directory_sizes).unwrap();
+
+    let is_cache_warning = |warnings: &[Error]| {
+        matches!(
+            warnings,
+            [Error::CannotUpdateCache(path, _)] if path == &trash.directory_sizes
+        )
+    };
+
+    let dir = root.join("dir");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("dummy"), dummy_bytes()).unwrap();
+
+    let trashed = crate::trash(&ctx, &dir).unwrap();
+    assert!(is_cache_warning(&trashed.warnings));
+    assert!(trashed.entry.trashed_path.is_dir());
+    assert!(trashed.entry.info_path.is_file());
+
+    let restored = crate::restore(&trashed.entry, false).unwrap();
+    assert!(is_cache_warning(&restored.warnings));
+    assert_eq!(restored.path, dir);
+    assert!(dir.join("dummy").is_file());
+    assert!(!trashed.entry.info_path.exists());
+
+    let trashed = crate::trash(&ctx, &dir).unwrap();
+    assert!(is_cache_warning(
+        &crate::purge_entry(&trashed.entry).unwrap()
+    ));
+    assert!(!trashed.entry.trashed_path.exists());
+    assert!(!trashed.entry.info_path.exists());
+}
+
+#[test]
+fn test_send_to_trash_resolves_the_path() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+    let trash = Trash::from_topdir(root, ctx.uid).unwrap();
+
+    assert!(trash::send_to_trash(&ctx, root.join("missing"), &trash).is_err());
+
+    let dir = root.join("dir");
+    fs::create_dir(&dir).unwrap();
+    symlink(&dir, root.join("link")).unwrap();
+    fs::write(dir.join("dummy"), dummy_bytes()).unwrap();
+
+    let entry = trash::send_to_trash(&ctx, root.join("link/dummy"), &trash)
+        .unwrap()
+        .entry;
+    assert_eq!(
+        entry.original_path,
+        dir.canonicalize().unwrap().join("dummy")
+    );
+}
+
+#[test]
+fn test_entries_keep_their_topdir() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let ctx = dummy_context(root);
+
+    // `$topdir/.Trash/$uid`, whose parent isn't the topdir
+    let shared_trash = root.join(".Trash");
+    fs::create_dir(&shared_trash).unwrap();
+    fs::set_permissions(&shared_trash, Permissions::from_mode(0o1777)).unwrap();
+    let trash = Trash::from_topdir(root, ctx.uid).unwrap();
+    assert_eq!(trash.root, shared_trash.join(ctx.uid.to_string()));
+
+    let dummy_path = root.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+    let entry = trash::send_to_trash(&ctx, dummy_path, &trash)
+        .unwrap()
+        .entry;
+
+    let listed = trash.entries().unwrap().entries.pop().unwrap();
+    assert_eq!(listed, entry);
+
+    for entry in &[entry, listed] {
+        let entry_trash = entry.trash().unwrap();
+        assert_eq!(entry_trash.root, trash.root);
+        assert_eq!(entry_trash.relative_paths_base(), Some(root));
+    }
+}
+
+#[test]
+fn test_bind_mounts_have_their_own_trash() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let source = root.join("source");
+    let target = root.join("target");
+    fs::create_dir(&source).unwrap();
+    fs::create_dir(&target).unwrap();
+
+    let _mount = match BindMount::new(&source, &target) {
+        Some(mount) => mount,
+        None => return,
+    };
+
+    let mut ctx = dummy_context(root);
+    // The deepest first, as `probe_mount_points` sorts them
+    ctx.mount_points.insert(
+        0,
+        MountPoint {
+            fs_path_prefix: target.clone(),
+            ..Default::default()
+        },
+    );
+
+    // Same device as the home trash, but rename(2) would fail with `EXDEV`
+    let dummy_path = target.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
+
+    assert_eq!(
+        ctx.mount_point_of_file(&dummy_path).unwrap().fs_path_prefix,
+        target
+    );
+    let trash = ctx.locate_trash_for(&dummy_path).unwrap();
+    assert_eq!(trash.topdir, Some(target.clone()));
+
+    let entry = crate::trash(&ctx, &dummy_path).unwrap().entry;
+    assert_eq!(
+        entry.trash().unwrap().root,
+        target.join(format!(".Trash-{}", ctx.uid))
+    );
+}
+
+#[test]
+fn test_plan_across_bind_mounts_copies() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let source = root.join("source");
+    let target = root.join("target");
+    fs::create_dir(&source).unwrap();
+    fs::create_dir(&target).unwrap();
+
+    let _mount = match BindMount::new(&source, &target) {
+        Some(mount) => mount,
+        None => return,
+    };
 
-    let foo_1 = dir_path.join(filename);
-    let mut file = File::create(&foo_1).unwrap();
-    file.write_all(&dummy_bytes()).unwrap();
-    assert!(foo_1.exists());
+    let mut ctx = dummy_context(root);
+    ctx.mount_points.insert(
+        0,
+        MountPoint {
+            fs_path_prefix: target.clone(),
+            ..Default::default()
+        },
+    );
 
-    let new_file_name = make_unique_file_name(filename, dir_path);
-    assert_eq!(new_file_name, OsString::from("foo-1"));
+    let dummy_path = target.join("dummy");
+    fs::write(&dummy_path, dummy_bytes()).unwrap();
 
-    let foo_2 = dir_path.join("foo-1");
-    let mut file = File::create(&foo_2).unwrap();
-    file.write_all(&dummy_bytes()).unwrap();
-    assert!(foo_2.exists());
+    // The home trash is on the same device, but not on the same mount
+    let plan = trash::plan_trash(&dummy_path, ctx.home_trash()).unwrap();
+    assert_eq!(plan.method, MoveMethod::CopyFallback);
 
-    let new_file_name = make_unique_file_name(filename, dir_path);
-    assert_eq!(new_file_name, OsString::from("foo-2"));
+    let plan = crate::plan(&ctx, &dummy_path, TrashOptions::default()).unwrap();
+    assert_eq!(plan.method, MoveMethod::Rename);
 
-    println!("{:?}", new_file_name);
+    // Which the actual move agrees with: a renamed file keeps its inode
+    let inode = fs::metadata(&dummy_path).unwrap().ino();
+    let entry = crate::trash(&ctx, &dummy_path).unwrap().entry;
+    assert_eq!(entry.trashed_path, plan.trashed_path);
+    assert_eq!(fs::metadata(&entry.trashed_path).unwrap().ino(), inode);
 }
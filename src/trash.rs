@@ -1,73 +1,319 @@
 use std::{
     convert::TryFrom,
-    ffi::OsString,
-    fs,
+    ffi::{OsStr, OsString},
+    fs, io,
+    os::unix::fs::DirBuilderExt,
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    error::{Error, Result},
-    info_file,
+    context::{FileMount, TrashContext},
+    directory_sizes,
+    error::{Error, InvalidDirectory, InvalidSharedTrash, Result},
+    info_file::{self, TrashEntry},
+    list::TrashListing,
+};
+use crate::{
+    ffi::{self, Dir, Lstat},
+    move_file::move_file_at,
 };
-use crate::{ffi::Lstat, move_file::move_file};
 
 use std::time::Duration;
 use unixstring::UnixString;
-use walkdir::WalkDir;
 
+/// A trash directory along with its `files` and `info` directories, opened.
+#[derive(Debug)]
+pub struct TrashDirs {
+    pub root: Dir,
+    pub files: Dir,
+    pub info: Dir,
+}
+
+/// Options which change how `tt::trash_with` handles a file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TrashOptions {
+    /// Trash the filesystem root, the home directory, mount points and trash directories
+    /// instead of refusing them
+    pub force_dangerous: bool,
+}
+
+/// How an item gets into `$trash/files`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveMethod {
+    /// A single rename(2), the item being on the same filesystem as the trash
+    Rename,
+    /// Copying the item, then deleting the original
+    CopyFallback,
+}
+
+/// A file which was sent to the trash
 #[derive(Debug)]
+pub struct Trashed {
+    /// The item as it now stands in the trash
+    pub entry: TrashEntry,
+    /// What went wrong once the item was in the trash, as `Error::CannotUpdateCache`
+    pub warnings: Vec<Error>,
+}
+
+/// Where `tt::trash` would send a file, as computed by `tt::plan`
+#[derive(Debug, Clone)]
+pub struct TrashPlan {
+    /// The file, its parent canonicalized
+    pub path: PathBuf,
+    /// The trash directory the file would go to, which may not exist yet
+    pub trash: Trash,
+    /// The path the file would have in `$trash/files`
+    pub trashed_path: PathBuf,
+    /// The path of the info file that would be written
+    pub info_path: PathBuf,
+    pub method: MoveMethod,
+}
+
+#[derive(Debug, Clone)]
 pub struct Trash {
+    pub root: PathBuf,
     pub files: PathBuf,
     pub directory_sizes: PathBuf,
     pub info: PathBuf,
+    /// The `$topdir` this trash directory belongs to,
+    /// or `None` for the home trash
+    pub topdir: Option<PathBuf>,
 }
 
 impl Trash {
     pub fn new(trash_root: &Path) -> Self {
         Self {
+            root: trash_root.to_owned(),
             files: trash_root.join("files"),
             directory_sizes: trash_root.join("directorysizes"),
             info: trash_root.join("info"),
+            topdir: None,
+        }
+    }
+
+    /// A trash directory found in the given `topdir`, whose info files
+    /// hold paths relative to `topdir`
+    pub fn with_topdir(trash_root: &Path, topdir: &Path) -> Self {
+        Self {
+            topdir: Some(topdir.to_owned()),
+            ..Self::new(trash_root)
+        }
+    }
+
+    /// The directory relative `Path=` values are to be resolved from.
+    ///
+    /// This is the `$topdir` for trash directories in mount points
+    /// and the directory the trash resides in (e.g. `$XDG_DATA_HOME`) for the home trash.
+    pub fn relative_paths_base(&self) -> Option<&Path> {
+        self.topdir.as_deref().or_else(|| self.root.parent())
+    }
+
+    /// Finds the trash directory to be used for files in the given `topdir`,
+    /// creating it if needed.
+    ///
+    /// From the FreeDesktop Trash spec 1.0:
+    ///
+    /// * If `$topdir/.Trash` exists, the trash directory is `$topdir/.Trash/$uid`.
+    /// * Otherwise, `$topdir/.Trash-$uid` is used.
+    pub fn from_topdir(topdir: &Path, uid: u32) -> Result<Self> {
+        let trash = Self::with_topdir(&topdir_trash_path(topdir, uid), topdir);
+
+        trash.ensure_exists(uid)?;
+
+        Ok(trash)
+    }
+
+    /// Creates whatever is missing of the trash directory and of its `files` and `info`
+    /// directories, making them private to the user given by `uid`.
+    ///
+    /// Existing directories must not be symbolic links and must be owned by the user,
+    /// so that nothing gets written somewhere else or readable by someone else.
+    pub fn ensure_exists(&self, uid: u32) -> Result<()> {
+        let parent_path = self
+            .root
+            .parent()
+            .ok_or_else(|| Error::FailedToObtainFileName(self.root.clone()))?;
+
+        // Such as `$XDG_DATA_HOME`, which should also be created with mode 0700
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent_path)?;
+
+        let parent = Dir::open(parent_path)?;
+        let root = ensure_private_dir(&parent, &self.root, uid)?;
+        ensure_private_dir(&root, &self.files, uid)?;
+        ensure_private_dir(&root, &self.info, uid)?;
+
+        Ok(())
+    }
+
+    /// Opens the trash directory, then `$trash/files` and `$trash/info`,
+    /// neither of which may be a symbolic link.
+    pub fn open_dirs(&self) -> Result<TrashDirs> {
+        let root = Dir::open(&self.root)?;
+
+        Ok(TrashDirs {
+            files: root.open_dir(OsStr::new("files"))?,
+            info: root.open_dir(OsStr::new("info"))?,
+            root,
+        })
+    }
+
+    /// Reads every `.trashinfo` file in `$trash/info`.
+    ///
+    /// Invalid info files are skipped, their errors being returned along with the entries.
+    pub fn entries(&self) -> Result<TrashListing> {
+        let mut listing = TrashListing::default();
+
+        for dir_entry in fs::read_dir(&self.info)? {
+            let info_file_path = dir_entry?.path();
+
+            match info_file::read_info_file(&info_file_path, self) {
+                Ok(entry) => listing.entries.push(entry),
+                Err(err) => listing.errors.push(err),
+            }
+        }
+
+        Ok(listing)
+    }
+}
+
+/// Creates the directory given by `path`, found in `parent`, with mode 0700 if it's missing,
+/// then checks that it's a directory owned by the user given by `uid`.
+fn ensure_private_dir(parent: &Dir, path: &Path, uid: u32) -> Result<Dir> {
+    let invalid = |reason| Error::InvalidTrashDirectory(path.to_owned(), reason);
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::FailedToObtainFileName(path.to_owned()))?;
+
+    match parent.create_dir(name, 0o700) {
+        // e.g. when trashing on the user's behalf as root
+        Ok(()) if ffi::effective_user_id() != uid => {
+            // Leaves the group unchanged
+            parent.chown(name, uid, u32::MAX)?;
         }
+        Ok(()) => {}
+        Err(Error::Io(err)) if err.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(err),
     }
+
+    let stat = parent.stat(name)?;
+
+    if stat.is_symlink() {
+        return Err(invalid(InvalidDirectory::IsSymlink));
+    }
+
+    if !stat.is_dir() {
+        return Err(invalid(InvalidDirectory::NotADirectory));
+    }
+
+    if stat.owner_user_id() != uid {
+        return Err(invalid(InvalidDirectory::WrongOwner));
+    }
+
+    parent.open_dir(name)
 }
 
-/// Renames the file given by `path` until a path
-/// not contained in `dir` is found.
+/// The trash directories already present in `topdir` for the given user ID.
 ///
-/// Example: if `foo` exists in `dir`, then this function returns `foo-1`
-///          if `foo` and `foo-1` exist in `dir`, then this function returns `foo-2`
-/// Note: assumes that `path` exists in `dir`!
-pub fn make_unique_file_name(path: &Path, dir: &Path) -> OsString {
-    let file_name = path.as_os_str();
-
-    for i in 1_u64.. {
-        let suffix = format!("-{}", i);
-        let mut new_file_name = file_name.to_owned();
-        new_file_name.push(suffix);
-        let path = dir.join(&new_file_name);
-        if !path.exists() {
-            return new_file_name;
+/// Unlike `Trash::from_topdir`, this never creates anything.
+pub fn existing_topdir_trashes(topdir: &Path, uid: u32) -> Vec<Trash> {
+    let shared_trash = topdir.join(".Trash");
+    let mut candidates = vec![topdir.join(format!(".Trash-{}", uid))];
+
+    if validate_shared_trash(&shared_trash, uid).is_ok() {
+        candidates.push(shared_trash.join(uid.to_string()));
+    }
+
+    candidates
+        .iter()
+        .map(|trash_root| Trash::with_topdir(trash_root, topdir))
+        .filter(|trash| trash.info.is_dir())
+        .collect()
+}
+
+/// The path of the trash directory for the given `topdir` and user ID.
+///
+/// Returns `$topdir/.Trash/$uid` if the administrator-provided
+/// `$topdir/.Trash` directory exists and is valid, or `$topdir/.Trash-$uid` otherwise.
+///
+/// See `shared_trash_problem` for why `$topdir/.Trash` was not used.
+pub fn topdir_trash_path(topdir: &Path, uid: u32) -> PathBuf {
+    let shared_trash = topdir.join(".Trash");
+
+    match validate_shared_trash(&shared_trash, uid) {
+        Ok(()) => shared_trash.join(uid.to_string()),
+        Err(_) => topdir.join(format!(".Trash-{}", uid)),
+    }
+}
+
+/// Why the administrator-provided `$topdir/.Trash` can't be used, as `Error::InvalidSharedTrash`,
+/// if it exists.
+pub fn shared_trash_problem(topdir: &Path, uid: u32) -> Option<Error> {
+    match validate_shared_trash(&topdir.join(".Trash"), uid) {
+        Err(err @ Error::InvalidSharedTrash(..)) => Some(err),
+        // `$topdir/.Trash` is valid, doesn't exist, or can't be accessed
+        _ => None,
+    }
+}
+
+/// Checks if the administrator-provided `$topdir/.Trash` given by `shared_trash` can be used.
+///
+/// From the FreeDesktop Trash spec 1.0:
+///
+///```text
+///   The implementation also MUST check that this directory is not a symbolic link.
+///   If this check fails, the implementation MUST NOT use this directory for trashing
+///   even if it does exist, and SHOULD report the failed check to the administrator
+///```
+///
+/// The sticky bit must also be set on `$topdir/.Trash`, and, if `$topdir/.Trash/$uid`
+/// already exists, it must be a directory owned by the user.
+pub fn validate_shared_trash(shared_trash: &Path, uid: u32) -> Result<()> {
+    let invalid = |path: &Path, reason| Error::InvalidSharedTrash(path.to_owned(), reason);
+
+    let stat = Lstat::lstat(&UnixString::try_from(shared_trash.to_owned())?)?;
+
+    if stat.is_symlink() {
+        return Err(invalid(shared_trash, InvalidSharedTrash::IsSymlink));
+    }
+
+    if !stat.is_dir() {
+        return Err(invalid(shared_trash, InvalidSharedTrash::NotADirectory));
+    }
+
+    if !stat.is_sticky() {
+        return Err(invalid(shared_trash, InvalidSharedTrash::NotSticky));
+    }
+
+    let user_trash = shared_trash.join(uid.to_string());
+    if let Ok(stat) = Lstat::lstat(&UnixString::try_from(user_trash.clone())?) {
+        if stat.is_symlink() {
+            return Err(invalid(&user_trash, InvalidSharedTrash::IsSymlink));
+        }
+
+        if !stat.is_dir() || stat.owner_user_id() != uid {
+            return Err(invalid(&user_trash, InvalidSharedTrash::WrongOwner));
         }
     }
 
-    unreachable!("control really shouldn't reach this")
+    Ok(())
 }
 
 /// Sends the file given by `path` to the given trash structure
-/// Assumes that `path` is canonicalized.
+/// Assumes that the parent of `path` is canonicalized.
 ///
 /// In case of success, returns the name of the trashed file
 /// exactly as sent to `TRASH/files`.
-fn _send_to_trash(path: &Path, trash: &Trash, deletion_date: Duration) -> Result<OsString> {
-
-    #[cfg(debug_assertions)]
-    dbg!(path, trash, &deletion_date);
-
-    let file_name = path
-        .file_name()
-        .ok_or_else(|| Error::FailedToObtainFileName(path.into()))?;
+fn _send_to_trash(
+    path: &Path,
+    trash: &Trash,
+    dirs: &TrashDirs,
+    deletion_date: Duration,
+) -> Result<OsString> {
+    let (source_dir, file_name) = Dir::open_parent(path)?;
 
     // Note:
     //
@@ -79,57 +325,178 @@ fn _send_to_trash(path: &Path, trash: &Trash, deletion_date: Duration) -> Result
     //```
     // Our implementation respects this by calling `build_info_file` before `move_file`
 
-    // Where the file will be sent to once trashed
-    let file_in_trash = trash.files.join(&file_name);
-
     // According to the trash-spec 1.0 states that, a file in the trash
     // must not be overwritten by a newer file with the same filename.
-    // For this reason, we'll make a new unique filename for the file we're deleting.
-    if file_in_trash.exists() {
-        let new_file_name = make_unique_file_name(file_name.as_ref(), &*trash.files);
-        let file_path = trash.files.join(&new_file_name);
-        info_file::build_info_file(path, &new_file_name, trash, deletion_date)?;
+    // For this reason, we'll try `foo`, `foo-1`, `foo-2` and so on until a free name is found.
+    //
+    // Creating the info file reserves a name atomically and the move never replaces
+    // anything, so two processes trashing same-named files can't clobber each other
+    for candidate in candidate_file_names(file_name) {
+        match write_info_and_move(path, &source_dir, trash, dirs, &candidate, deletion_date) {
+            Ok(()) => return Ok(candidate),
+            // Taken in either `$trash/info` or `$trash/files`
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    unreachable!("control really shouldn't reach this")
+}
+
+/// `file_name`, followed by `file_name-1`, `file_name-2` and so on.
+pub(crate) fn candidate_file_names(file_name: &OsStr) -> impl Iterator<Item = OsString> + '_ {
+    let suffixed = (1_u64..).map(move |i| {
+        let mut new_file_name = file_name.to_owned();
+        new_file_name.push(format!("-{}", i));
+        new_file_name
+    });
+
+    std::iter::once(file_name.to_owned()).chain(suffixed)
+}
+
+/// Writes the info file for `path`, then moves `path` (found in `source_dir`)
+/// to `$trash/files/$file_name`.
+///
+/// Fails with `EEXIST` if `file_name` is already taken in either directory.
+/// If the move fails, the info file is removed again, unless the item did make it
+/// into `$trash/files` (which happens when the copy fallback can't delete the source).
+fn write_info_and_move(
+    path: &Path,
+    source_dir: &Dir,
+    trash: &Trash,
+    dirs: &TrashDirs,
+    file_name: &OsStr,
+    deletion_date: Duration,
+) -> Result<()> {
+    info_file::write_info_file(&dirs.info, path, file_name, trash, deletion_date)?;
 
-        move_file(path, &*file_path)?;
+    let source_name = path
+        .file_name()
+        .ok_or_else(|| Error::FailedToObtainFileName(path.into()))?;
+
+    if let Err(err) = move_file_at(source_dir, source_name, &dirs.files, file_name) {
+        let name_taken =
+            matches!(&err, Error::Io(err) if err.kind() == io::ErrorKind::AlreadyExists);
 
-        return Ok(new_file_name);
+        // Unless the name was taken beforehand, whatever is in `$trash/files` got there
+        // from `path`, since our info file reserved the name
+        if name_taken || dirs.files.stat(file_name).is_err() {
+            let _ = dirs
+                .info
+                .unlink(&info_file::make_info_file_name(file_name), false);
+        }
+        return Err(err);
     }
 
-    println!(
-        "Files: {}, path: {}, new-path: {}",
-        trash.files.display(),
-        path.display(),
-        file_in_trash.display()
-    );
+    Ok(())
+}
 
-    info_file::build_info_file(path, file_name, trash, deletion_date)?;
-    move_file(path, &file_in_trash)?;
+/// Makes `path` absolute by canonicalizing its parent directory only.
+///
+/// Unlike `fs::canonicalize`, this doesn't follow `path` itself if it's a symbolic link,
+/// so that the link gets trashed rather than its target, as `rm` would do.
+pub fn resolve_parent(path: &Path) -> Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::FailedToObtainFileName(path.into()))?;
 
-    Ok(file_name.into())
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        // A bare file name, relative to the current directory
+        _ => Path::new("."),
+    };
+
+    let resolved = parent.canonicalize()?.join(file_name);
+
+    // Fails if `path` doesn't exist, without following it
+    fs::symlink_metadata(&resolved)?;
+
+    Ok(resolved)
 }
 
-/// Sends a file to trash
-pub fn send_to_trash(to_be_removed: PathBuf, trash: &Trash) -> Result<()> {
-    // Assumes that `path` is canonicalized
-    let path = to_be_removed;
-    debug_assert!(fs::canonicalize(&path).unwrap() == path);
+/// Works out where the file given by `path` would be trashed to, without touching anything.
+/// Assumes that the parent of `path` is canonicalized.
+///
+/// The name is picked as `_send_to_trash` would, the first of `foo`, `foo-1` and so on
+/// that's free in both `$trash/files` and `$trash/info`. The rename is expected to work
+/// if the file lives on the same mount as the trash (see `FileMount`).
+pub fn plan_trash(path: &Path, trash: Trash) -> Result<TrashPlan> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::FailedToObtainFileName(path.into()))?;
+
+    let is_taken = |name: &OsStr| {
+        trash.files.join(name).symlink_metadata().is_ok()
+            || info_file::make_info_file_path(name, &trash.info)
+                .symlink_metadata()
+                .is_ok()
+    };
+
+    let file_name = candidate_file_names(file_name)
+        .find(|candidate| !is_taken(candidate))
+        .expect("there are endless candidates");
 
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("it seems that time went backwards!");
+    // As rename(2) would fail with `EXDEV` across mounts, even of a same filesystem
+    let trash_mount = FileMount::of_existing_ancestor(&trash.files)?;
+    let method = if trash_mount.is_same_mount(&FileMount::of(path)?) {
+        MoveMethod::Rename
+    } else {
+        MoveMethod::CopyFallback
+    };
+
+    Ok(TrashPlan {
+        path: path.to_owned(),
+        trashed_path: trash.files.join(&file_name),
+        info_path: info_file::make_info_file_path(&file_name, &trash.info),
+        trash,
+        method,
+    })
+}
+
+/// Sends a file to trash, its deletion date being given by the clock of `ctx`.
+///
+/// Symbolic links are trashed themselves, never their targets. The parent of `to_be_removed`
+/// is resolved first, so it may be relative or go through symbolic links.
+///
+/// Failing to update the `directorysizes` cache doesn't make the whole operation fail,
+/// the item being in the trash by then: it's returned as a warning instead.
+pub fn send_to_trash(ctx: &TrashContext, to_be_removed: PathBuf, trash: &Trash) -> Result<Trashed> {
+    // Relative paths, or paths going through symbolic links, would otherwise end up
+    // as such in the info file
+    let path = resolve_parent(&to_be_removed)?;
 
-    let _file_name = _send_to_trash(&path, trash, now)?;
+    let now = ctx.clock.now();
 
-    if path.is_dir() {
-        // TODO: Update directorysizes
-        // update_directory_size_cache()
-        let _dir_size = directory_size(&path)?;
+    // `symlink_metadata` so that links to directories don't count as directories
+    let is_dir = fs::symlink_metadata(&path)?.is_dir();
+
+    // Every operation happens relative to these, so that renaming any of their
+    // ancestors meanwhile can't redirect anything
+    let dirs = trash.open_dirs()?;
+
+    let file_name = _send_to_trash(&path, trash, &dirs, now)?;
+
+    let mut warnings = Vec::new();
+
+    if is_dir {
+        warnings.extend(directory_sizes::record_entry(trash, &dirs, &file_name));
     }
 
-    Ok(())
+    let entry = TrashEntry {
+        trashed_path: trash.files.join(&file_name),
+        info_path: info_file::make_info_file_path(&file_name, &trash.info),
+        original_path: path,
+        deletion_date: ffi::format_time(now)?,
+        trashed_name: file_name,
+        topdir: trash.topdir.clone(),
+    };
+
+    Ok(Trashed { entry, warnings })
 }
 
-// TODO: add a test for this
+/// The disk space used by the directory given by `path` and its contents, in bytes.
+///
+/// This is calculated in the same way as `du -B1` would.
 pub fn directory_size(path: impl AsRef<Path>) -> Result<u64> {
     let path = path.as_ref();
 
@@ -137,16 +504,67 @@ pub fn directory_size(path: impl AsRef<Path>) -> Result<u64> {
         return Err(Error::NotADirectory(path.to_owned()));
     }
 
-    let dir_size_in_blocks = WalkDir::new(path)
-        .into_iter()
-        .flatten()
-        .map(|e| e.path().to_owned())
-        .map(UnixString::try_from)
-        .flatten()
-        .map(|x| Lstat::lstat(&x))
-        .flatten()
-        .map(|file| file.blocks() as u64)
-        .sum();
+    let path = path.canonicalize()?;
+    let (parent, name) = Dir::open_parent(&path)?;
+
+    directory_size_at(&parent, name)
+}
+
+/// The disk space used by the directory `name` within `parent` and its contents, in bytes,
+/// measured as `directory_size` does. Symbolic links are never followed.
+pub fn directory_size_at(parent: &Dir, name: &OsStr) -> Result<u64> {
+    let stat = parent.stat(name)?;
+
+    if !stat.is_dir() {
+        return Err(Error::NotADirectory(name.into()));
+    }
+
+    let mut size = disk_usage(&stat);
+    let root = parent.open_dir(name)?;
+    let subdirs = measure_entries(&root, &mut size)?;
+
+    // Depth-first, so that only the directories along the current branch are kept open
+    let mut branch = vec![(root, subdirs)];
+
+    while let Some((dir, subdirs)) = branch.last_mut() {
+        let subdir = match subdirs.pop() {
+            Some(subdir) => dir.open_dir(&subdir),
+            None => {
+                branch.pop();
+                continue;
+            }
+        };
+
+        // Subdirectories which can't be read are skipped, as `du` would
+        if let Ok(subdir) = subdir {
+            let subdirs = measure_entries(&subdir, &mut size).unwrap_or_default();
+            branch.push((subdir, subdirs));
+        }
+    }
+
+    Ok(size)
+}
+
+/// Adds the disk usage of every entry of `dir` to `size`,
+/// returning the names of its subdirectories.
+fn measure_entries(dir: &Dir, size: &mut u64) -> Result<Vec<OsString>> {
+    let mut subdirs = Vec::new();
+
+    for name in dir.entries()? {
+        // Entries removed meanwhile are skipped
+        if let Ok(stat) = dir.stat(&name) {
+            *size += disk_usage(&stat);
+
+            if stat.is_dir() {
+                subdirs.push(name);
+            }
+        }
+    }
+
+    Ok(subdirs)
+}
 
-    Ok(dir_size_in_blocks)
+/// `st_blocks` is always given in 512-byte units
+fn disk_usage(stat: &Lstat) -> u64 {
+    stat.blocks() as u64 * 512
 }